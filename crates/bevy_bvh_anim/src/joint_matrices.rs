@@ -55,7 +55,8 @@ impl<J: JointTrait> JointMatrices<J> {
 
     /// Applies a single frame from the bvh to all matrices.
     pub fn apply_frame(&mut self, frame: &[f32]) {
-        for (i, joint) in self.joints.iter().enumerate() {
+        for i in 0..self.joints.len() {
+            let joint = &self.joints[i];
             let mut euler = Vec3::ZERO;
             let mut translation = joint.offset();
 
@@ -75,17 +76,45 @@ impl<J: JointTrait> JointMatrices<J> {
             let rotation = Quat::from_euler(EulerRot::XYZ, euler.x, euler.y, euler.z);
             // Local matrix of the current joint
             let local_matrix = Mat4::from_rotation_translation(rotation, translation);
-            self.local_matrices[i] = local_matrix;
+            self.set_local_matrix(i, local_matrix);
+        }
+    }
 
-            match joint.parent_index() {
-                Some(parent_index) => {
-                    let parent_matrix = self.world_matrices[parent_index];
-                    self.world_matrices[i] = Mat4::mul_mat4(&parent_matrix, &local_matrix);
-                }
-                None => {
-                    self.world_matrices[i] = local_matrix;
+    /// Applies the position and rotation of each joint to all matrices.
+    ///
+    /// Like [`Self::apply_frame`], positions only replace the offset
+    /// along the axes that have a position channel.
+    pub fn apply_pos_rots(&mut self, pos_rots: &[(Vec3, Quat)]) {
+        for (i, &(position, rotation)) in pos_rots.iter().enumerate().take(self.joints.len()) {
+            let mut translation = self.joints[i].offset();
+
+            for channel in self.joints[i].channels() {
+                match channel.channel_type() {
+                    ChannelType::PositionX => translation.x = position.x,
+                    ChannelType::PositionY => translation.y = position.y,
+                    ChannelType::PositionZ => translation.z = position.z,
+                    _ => {}
                 }
             }
+
+            // Local matrix of the current joint
+            let local_matrix = Mat4::from_rotation_translation(rotation, translation);
+            self.set_local_matrix(i, local_matrix);
+        }
+    }
+
+    /// Set the local matrix of a joint and update its world matrix from its parent.
+    fn set_local_matrix(&mut self, joint_index: usize, local_matrix: Mat4) {
+        self.local_matrices[joint_index] = local_matrix;
+
+        match self.joints[joint_index].parent_index() {
+            Some(parent_index) => {
+                let parent_matrix = self.world_matrices[parent_index];
+                self.world_matrices[joint_index] = Mat4::mul_mat4(&parent_matrix, &local_matrix);
+            }
+            None => {
+                self.world_matrices[joint_index] = local_matrix;
+            }
         }
     }
 
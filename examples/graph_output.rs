@@ -0,0 +1,98 @@
+//! Blend the motion matched pose with an authored clip inside an [`AnimationGraph`].
+//!
+//! Enter play mode, the right arm of the character waves on top of the motion matched pose.
+
+use bevy::animation::{animated_field, AnimationTarget};
+use bevy::prelude::*;
+use bevy_motion_matching::bvh_manager::bvh_player::JointMap;
+use bevy_motion_matching::motion::graph_output::MotionGraphOutput;
+use bevy_motion_matching::motion::motion_player::MotionPlayer;
+use bevy_motion_matching::MotionMatchingAppPlugin;
+
+/// Joint animated by the authored clip.
+const WAVE_JOINT: &str = "Model_RightArm";
+/// Weight of the authored clip relative to the motion matched pose.
+const WAVE_WEIGHT: f32 = 0.6;
+
+fn main() -> AppExit {
+    App::new()
+        .add_plugins(MotionMatchingAppPlugin)
+        .add_systems(Update, (setup_graph, author_wave_clip))
+        .run()
+}
+
+/// Clip node that is authored once the target of [`WAVE_JOINT`] is known.
+#[derive(Component)]
+struct WaveClip(Handle<AnimationClip>);
+
+/// Play the motion matched pose and the wave clip side by side in the same graph.
+fn setup_graph(
+    mut commands: Commands,
+    q_players: Query<
+        Entity,
+        (
+            With<MotionPlayer>,
+            With<JointMap>,
+            Without<MotionGraphOutput>,
+        ),
+    >,
+    mut graphs: ResMut<Assets<AnimationGraph>>,
+    mut clips: ResMut<Assets<AnimationClip>>,
+) {
+    for entity in q_players.iter() {
+        let mut graph = AnimationGraph::new();
+        let root = graph.root;
+
+        let output = MotionGraphOutput::add_to_graph(&mut graph, &mut clips, root, 1.0);
+        let wave_clip = clips.add(AnimationClip::default());
+        let wave_node = graph.add_clip(wave_clip.clone(), WAVE_WEIGHT, root);
+
+        let mut animation_player = AnimationPlayer::default();
+        animation_player.play(output.node()).repeat();
+        animation_player.play(wave_node).repeat();
+
+        commands.entity(entity).insert((
+            output,
+            WaveClip(wave_clip),
+            animation_player,
+            AnimationGraphHandle(graphs.add(graph)),
+        ));
+    }
+}
+
+/// Swing [`WAVE_JOINT`] back and forth around its rest rotation.
+fn author_wave_clip(
+    mut commands: Commands,
+    q_players: Query<(&WaveClip, &JointMap, Entity)>,
+    q_targets: Query<(&AnimationTarget, &Transform)>,
+    mut clips: ResMut<Assets<AnimationClip>>,
+) {
+    for (wave_clip, joint_map, entity) in q_players.iter() {
+        // The target is assigned while the output clip is built.
+        let Some((target, transform)) = joint_map
+            .get(WAVE_JOINT)
+            .and_then(|joint| q_targets.get(*joint).ok())
+        else {
+            continue;
+        };
+
+        let rest = transform.rotation;
+        let Ok(curve) = AnimatableKeyframeCurve::new([
+            (0.0, rest * Quat::from_rotation_x(-0.6)),
+            (0.5, rest * Quat::from_rotation_x(0.6)),
+            (1.0, rest * Quat::from_rotation_x(-0.6)),
+        ]) else {
+            continue;
+        };
+
+        let Some(clip) = clips.get_mut(&wave_clip.0) else {
+            continue;
+        };
+        clip.add_curve_to_target(
+            target.id,
+            AnimatableCurve::new(animated_field!(Transform::rotation), curve),
+        );
+
+        commands.entity(entity).remove::<WaveClip>();
+    }
+}
@@ -31,6 +31,7 @@ fn main() -> AppExit {
     .insert_resource(MovementConfig {
         walk_speed: 1.0,
         run_speed: 2.0,
+        sprint_speed: 3.0,
         lerp_factor: 10.0,
     })
     .insert_resource(TrajectoryConfig {
@@ -59,12 +60,11 @@ fn main() -> AppExit {
 
 // TODO: Remove this
 fn movement_test(
-    mut q_movements: Query<(&mut Transform2d, &MovementDirection)>,
-    movement_config: Res<MovementConfig>,
+    mut q_movements: Query<(&mut Transform2d, &MovementDirection, &MovementSpeed)>,
     time: Res<Time>,
 ) {
-    for (mut transform2d, direction) in q_movements.iter_mut() {
-        transform2d.translation += **direction * movement_config.walk_speed * time.delta_secs();
+    for (mut transform2d, direction, movement_speed) in q_movements.iter_mut() {
+        transform2d.translation += **direction * movement_speed.get() * time.delta_secs();
     }
 }
 
@@ -76,6 +76,7 @@ fn setup(
     commands.spawn((
         Mesh3d(meshes.add(Cuboid::from_size(Vec3::splat(0.1)))),
         MeshMaterial3d(materials.add(Color::WHITE)),
+        PlayerBundle::default(),
         TrajectoryBundle::new(100),
     ));
 }
@@ -16,6 +16,7 @@ pub enum PlayerAction {
     #[actionlike(DualAxis)]
     Walk,
     Run,
+    Sprint,
 }
 
 impl PlayerAction {
@@ -26,10 +27,12 @@ impl PlayerAction {
         // Default gamepad input bindings
         input_map.insert_dual_axis(Self::Walk, GamepadStick::LEFT);
         input_map.insert(Self::Run, GamepadButton::South);
+        input_map.insert(Self::Sprint, GamepadButton::LeftThumb);
 
         // Default kbm input bindings
         input_map.insert_dual_axis(Self::Walk, VirtualDPad::wasd());
         input_map.insert(Self::Run, KeyCode::ShiftLeft);
+        input_map.insert(Self::Sprint, KeyCode::ControlLeft);
 
         input_map
     }
@@ -3,7 +3,7 @@ use bevy_bvh_anim::bvh_anim::ChannelType;
 use bevy_bvh_anim::prelude::*;
 
 use crate::draw_axes::{ColorPalette, DrawAxes};
-use crate::motion::pose_data::Pose;
+use crate::motion::pose_data::{JointPose, Pose};
 use crate::player::MovementConfig;
 use crate::scene_loader::MainScene;
 use crate::ui::config::{BvhTrailConfig, DrawMainArmature};
@@ -136,11 +136,11 @@ fn bvh_trail_gizmos(
         return;
     };
 
-    let mut joint_matrices = JointMatrices::new(
-        &bvh.joints()
-            .map(|joint| joint.data().clone())
-            .collect::<Vec<_>>(),
-    );
+    let joints = bvh
+        .joints()
+        .map(|joint| joint.data().clone())
+        .collect::<Vec<_>>();
+    let mut joint_matrices = JointMatrices::new(&joints);
 
     let frame_time = bvh.frame_time().as_secs_f32();
     let total_duration = frame_time * bvh.num_frames() as f32;
@@ -161,11 +161,11 @@ fn bvh_trail_gizmos(
 
         let leak = time - frame_time * index as f32;
         let factor = leak / frame_time;
-        let curr_pose = Pose::from_frame(curr_frame);
-        let next_pose = Pose::from_frame(next_frame);
-        let pose = Pose::lerp(&curr_pose, &next_pose, factor);
+        let curr_pose = JointPose::sample(&Pose::from_frame(curr_frame), &joints);
+        let next_pose = JointPose::sample(&Pose::from_frame(next_frame), &joints);
+        let pose = curr_pose.blend(&next_pose, factor);
 
-        joint_matrices.apply_frame(&pose);
+        joint_matrices.apply_pos_rots(&pose);
 
         // Draw trajectory.
         if config.draw_trajectory {
@@ -215,7 +215,7 @@ fn bvh_trail_gizmos(
                 velocity_magnitude * 0.1,
                 palette.purple.mix(
                     &palette.orange,
-                    velocity_magnitude / movement_config.run_speed,
+                    velocity_magnitude / movement_config.sprint_speed,
                 ),
             );
         }
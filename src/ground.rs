@@ -0,0 +1,174 @@
+//! Adapt characters to the height of the ground below them.
+
+use std::f32::consts::LN_2;
+use std::marker::PhantomData;
+
+use bevy::ecs::system::{StaticSystemParam, SystemParam, SystemParamItem};
+use bevy::picking::mesh_picking::ray_cast::{MeshRayCast, RayCastSettings, RayCastVisibility};
+use bevy::prelude::*;
+
+use crate::motion::foot_ik::FootIk;
+use crate::motion::motion_player::{MotionPlayer, MotionPlayerSet};
+use crate::transform2d::Transform2d;
+use crate::{GameMode, MotionUpdate};
+
+/// Adapt characters to the ground using the [`GroundQuery`] `G`.
+pub struct GroundPlugin<G>(PhantomData<G>);
+
+impl<G> Default for GroundPlugin<G> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<G> Plugin for GroundPlugin<G>
+where
+    G: SystemParam + Send + Sync + 'static,
+    for<'w, 's> SystemParamItem<'w, 's, G>: GroundQuery,
+{
+    fn build(&self, app: &mut App) {
+        app.insert_resource(GroundConfig {
+            probe_height: 0.5,
+            height_halflife: 0.05,
+            max_pelvis_drop: 0.3,
+        })
+        .add_systems(PreUpdate, init_ground_height)
+        .add_systems(
+            MotionUpdate,
+            adapt_to_ground::<G>
+                .after(MotionPlayerSet::Inertialize)
+                .before(MotionPlayerSet::FootIk)
+                .run_if(in_state(GameMode::Play)),
+        );
+    }
+}
+
+fn init_ground_height(mut commands: Commands, q_players: Query<Entity, Added<MotionPlayer>>) {
+    for entity in q_players.iter() {
+        commands.entity(entity).insert(GroundHeight::default());
+    }
+}
+
+/// Place the root on the ground and lower the pelvis so that the feet can reach
+/// the ground below them.
+fn adapt_to_ground<G>(
+    mut ground: StaticSystemParam<G>,
+    mut q_characters: Query<(
+        &Transform2d,
+        &mut Transform,
+        &mut GroundHeight,
+        Option<&mut FootIk>,
+    )>,
+    config: Res<GroundConfig>,
+    time: Res<Time>,
+) where
+    G: SystemParam + 'static,
+    for<'w, 's> SystemParamItem<'w, 's, G>: GroundQuery,
+{
+    let factor =
+        1.0 - f32::exp(-LN_2 * time.delta_secs() / config.height_halflife.max(f32::EPSILON));
+
+    for (transform2d, mut transform, mut ground_height, foot_ik) in q_characters.iter_mut() {
+        let probe_height = ground_height.height + config.probe_height;
+
+        if let Some(height) = ground.ground_height(transform2d.translation, probe_height) {
+            ground_height.height = f32::lerp(ground_height.height, height, factor);
+        }
+
+        // Height of the ground below each foot relative to the root.
+        let ground_offsets = foot_ik.as_ref().map(|foot_ik| {
+            foot_ik
+                .leg_targets()
+                .map(|target| {
+                    ground
+                        .ground_height(target.xz(), probe_height)
+                        .map(|height| height - ground_height.height)
+                        .unwrap_or_default()
+                        .clamp(-config.probe_height, config.probe_height)
+                })
+                .collect::<Vec<_>>()
+        });
+
+        // Lower the pelvis for the lowest foot, the other feet are lifted by the IK.
+        let pelvis_offset = ground_offsets
+            .iter()
+            .flatten()
+            .fold(0.0, |offset, &ground_offset| {
+                f32::min(offset, ground_offset)
+            })
+            .max(-config.max_pelvis_drop);
+
+        ground_height.pelvis_offset = f32::lerp(ground_height.pelvis_offset, pelvis_offset, factor);
+        transform.translation.y = ground_height.height + ground_height.pelvis_offset;
+
+        if let (Some(mut foot_ik), Some(ground_offsets)) = (foot_ik, ground_offsets) {
+            foot_ik.set_ground_offsets(ground_height.pelvis_offset, &ground_offsets);
+        }
+    }
+}
+
+/// Height of the ground at a given position, implemented by the game.
+pub trait GroundQuery {
+    /// Height of the highest ground at `translation` (on the xz plane) below `max_height`.
+    fn ground_height(&mut self, translation: Vec2, max_height: f32) -> Option<f32>;
+}
+
+/// [`GroundQuery`] ray casting downwards onto the meshes marked with [`Ground`].
+#[derive(SystemParam)]
+pub struct MeshGround<'w, 's> {
+    ray_cast: MeshRayCast<'w, 's>,
+    q_grounds: Query<'w, 's, (), With<Ground>>,
+}
+
+impl GroundQuery for MeshGround<'_, '_> {
+    fn ground_height(&mut self, translation: Vec2, max_height: f32) -> Option<f32> {
+        let ray = Ray3d::new(
+            Vec3::new(translation.x, max_height, translation.y),
+            Dir3::NEG_Y,
+        );
+
+        let filter = |entity| self.q_grounds.contains(entity);
+        let settings = RayCastSettings::default()
+            .with_visibility(RayCastVisibility::Any)
+            .with_filter(&filter);
+
+        self.ray_cast
+            .cast_ray(ray, &settings)
+            .first()
+            .map(|(_, hit)| hit.point.y)
+    }
+}
+
+/// Marks meshes that characters can stand on.
+#[derive(Component, Default, Debug)]
+pub struct Ground;
+
+/// Ground height below a character.
+#[derive(Component, Default, Debug)]
+pub struct GroundHeight {
+    /// Smoothed height of the ground below the root.
+    height: f32,
+    /// Smoothed offset of the pelvis to let the feet reach lower ground.
+    pelvis_offset: f32,
+}
+
+// Getters
+impl GroundHeight {
+    pub fn height(&self) -> f32 {
+        self.height
+    }
+
+    pub fn pelvis_offset(&self) -> f32 {
+        self.pelvis_offset
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct GroundConfig {
+    /// Maximum step up (and down) the ground is searched from.
+    pub probe_height: f32,
+    /// Halflife of smoothing the ground height.
+    pub height_halflife: f32,
+    /// Maximum distance the pelvis can be lowered.
+    pub max_pelvis_drop: f32,
+}
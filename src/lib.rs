@@ -1,3 +1,4 @@
+use bevy::ecs::schedule::ScheduleLabel;
 use bevy::prelude::*;
 use trajectory::Velocity;
 use transform2d::Transform2d;
@@ -6,12 +7,18 @@ pub mod action;
 pub mod bvh_manager;
 pub mod camera;
 pub mod draw_axes;
+pub mod ground;
 pub mod motion;
 pub mod motion_matching;
+pub mod path_follow;
 pub mod player;
 pub mod record;
+pub mod replay;
 pub mod scene_loader;
+#[cfg(test)]
+mod test_app;
 pub mod testing;
+pub mod timestep;
 pub mod trajectory;
 pub mod transform2d;
 pub mod ui;
@@ -25,7 +32,7 @@ pub struct MotionMatchingAppPlugin;
 impl Plugin for MotionMatchingAppPlugin {
     fn build(&self, app: &mut App) {
         app.configure_sets(
-            Update,
+            MotionUpdate,
             (
                 MainSet::Action,
                 MainSet::Record,
@@ -56,7 +63,13 @@ impl Plugin for MotionMatchingAppPlugin {
             draw_axes::DrawAxesPlugin,
             visualization::VisualizationPlugin,
         ))
-        .add_plugins(testing::TestingPlugin);
+        .add_plugins((
+            ground::GroundPlugin::<ground::MeshGround>::default(),
+            testing::TestingPlugin,
+            timestep::TimestepPlugin,
+            replay::ReplayPlugin,
+            path_follow::PathFollowPlugin,
+        ));
 
         app.init_state::<GameMode>().init_state::<Method>();
     }
@@ -70,6 +83,11 @@ pub enum GameMode {
     Play,
 }
 
+/// Schedule of the [`MainSet`], run every frame or at a fixed timestep
+/// (see [`timestep::MotionTimestep`]).
+#[derive(ScheduleLabel, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MotionUpdate;
+
 #[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MainSet {
     Action,
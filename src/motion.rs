@@ -1,11 +1,17 @@
 use bevy::prelude::*;
 use motion_asset::MotionAsset;
 
+pub mod animation_layers;
+pub mod animation_notify;
 pub mod chunk;
+pub mod foot_ik;
+pub mod graph_output;
+pub mod inertialization;
 pub mod joint_info;
 pub mod motion_asset;
 pub mod motion_player;
 pub mod pose_data;
+pub mod simulation_bone;
 pub mod trajectory_data;
 
 pub struct MotionPlugin;
@@ -15,6 +21,12 @@ impl Plugin for MotionPlugin {
         app.add_plugins((
             motion_asset::MotionAssetPlugin,
             motion_player::MotionPlayerPlugin,
+            animation_layers::AnimationLayersPlugin,
+            inertialization::InertializationPlugin,
+            simulation_bone::SimulationBonePlugin,
+            foot_ik::FootIkPlugin,
+            animation_notify::AnimationNotifyPlugin,
+            graph_output::GraphOutputPlugin,
         ));
     }
 }
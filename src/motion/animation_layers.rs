@@ -0,0 +1,312 @@
+//! Weighted per-joint layers applied on top of the matched pose.
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::bvh_manager::bvh_player::JointMap;
+use crate::{GameMode, MotionUpdate};
+
+use super::chunk::ChunkIterator;
+use super::joint_info::JointInfo;
+use super::motion_player::{MotionPlayerSet, MotionPose};
+use super::MotionData;
+
+pub(super) struct AnimationLayersPlugin;
+
+impl Plugin for AnimationLayersPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            MotionUpdate,
+            (advance_layer_clips, apply_animation_layers)
+                .chain()
+                .in_set(MotionPlayerSet::ApplyLayers)
+                .run_if(in_state(GameMode::Play)),
+        );
+    }
+}
+
+fn advance_layer_clips(
+    motion_data: MotionData,
+    mut q_animation_layers: Query<&mut AnimationLayers>,
+    time: Res<Time>,
+) {
+    let Some(motion_asset) = motion_data.get() else {
+        return;
+    };
+
+    for mut animation_layers in q_animation_layers.iter_mut() {
+        for layer in animation_layers.0.iter_mut() {
+            let LayerSource::Clip {
+                motion_pose,
+                looping,
+            } = &mut layer.source
+            else {
+                continue;
+            };
+
+            let Some(duration) = motion_asset
+                .pose_data
+                .chunk_duration(motion_pose.chunk_index)
+            else {
+                continue;
+            };
+
+            motion_pose.time += time.delta_secs();
+            motion_pose.time = match *looping && duration > 0.0 {
+                true => motion_pose.time % duration,
+                false => f32::min(motion_pose.time, duration),
+            };
+        }
+    }
+}
+
+/// Blend the rotations of each layer into the joint transforms, in order.
+fn apply_animation_layers(
+    motion_data: MotionData,
+    q_animation_layers: Query<(&AnimationLayers, &JointMap)>,
+    mut q_transforms: Query<&mut Transform>,
+) {
+    let Some(motion_asset) = motion_data.get() else {
+        return;
+    };
+
+    // Joint indices by name, shared by all clip layers.
+    let joint_indices = motion_asset
+        .joints()
+        .iter()
+        .enumerate()
+        .map(|(joint_index, joint)| (joint.name(), (joint_index, joint)))
+        .collect::<HashMap<_, _>>();
+
+    for (animation_layers, joint_map) in q_animation_layers.iter() {
+        for layer in animation_layers.0.iter() {
+            if layer.weight <= 0.0 {
+                continue;
+            }
+
+            // Sampled clip pose and the reference pose additive clips are relative to.
+            let clip_poses = match &layer.source {
+                LayerSource::Clip { motion_pose, .. } => {
+                    let Some((pose, reference)) = motion_pose
+                        .get_pose(&motion_asset.pose_data, motion_asset.joints())
+                        .zip(
+                            motion_asset
+                                .pose_data
+                                .get_chunk(motion_pose.chunk_index)
+                                .and_then(|poses| poses.first()),
+                        )
+                    else {
+                        continue;
+                    };
+                    Some((pose, reference))
+                }
+                LayerSource::Procedural(_) => None,
+            };
+
+            for (name, mask_weight) in layer.mask.iter() {
+                let weight = f32::clamp(layer.weight * mask_weight, 0.0, 1.0);
+                if weight <= 0.0 {
+                    continue;
+                }
+
+                let rotation = match (&layer.source, &clip_poses) {
+                    (LayerSource::Procedural(rotations), _) => rotations.get(name).copied(),
+                    (LayerSource::Clip { .. }, Some((pose, reference))) => {
+                        joint_indices.get(name).map(|&(joint_index, joint)| {
+                            let (_, rotation) = pose.get_pos_rot(joint_index);
+                            match layer.blend {
+                                LayerBlend::Override => rotation,
+                                LayerBlend::Additive => {
+                                    reference.get_rot(joint).inverse() * rotation
+                                }
+                            }
+                        })
+                    }
+                    (LayerSource::Clip { .. }, None) => None,
+                };
+
+                let Some(rotation) = rotation else {
+                    continue;
+                };
+
+                let Some(mut transform) = joint_map
+                    .get(name)
+                    .and_then(|entity| q_transforms.get_mut(*entity).ok())
+                else {
+                    continue;
+                };
+
+                transform.rotation = match layer.blend {
+                    LayerBlend::Override => Quat::slerp(transform.rotation, rotation, weight),
+                    LayerBlend::Additive => {
+                        transform.rotation * Quat::slerp(Quat::IDENTITY, rotation, weight)
+                    }
+                };
+            }
+        }
+    }
+}
+
+/// Layers contributing to the joints of a motion player after the matched pose is applied.
+///
+/// Layers are applied in order, each on top of the result of the ones before it.
+#[derive(Component, Debug, Default)]
+pub struct AnimationLayers(Vec<AnimationLayer>);
+
+impl AnimationLayers {
+    /// Add a layer on top of the existing ones.
+    pub fn push(&mut self, layer: AnimationLayer) {
+        self.0.push(layer);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<AnimationLayer> {
+        let index = self.0.iter().position(|layer| layer.name == name)?;
+        Some(self.0.remove(index))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AnimationLayer> {
+        self.0.iter().find(|layer| layer.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut AnimationLayer> {
+        self.0.iter_mut().find(|layer| layer.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AnimationLayer> {
+        self.0.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut AnimationLayer> {
+        self.0.iter_mut()
+    }
+}
+
+#[derive(Debug)]
+pub struct AnimationLayer {
+    pub name: String,
+    pub blend: LayerBlend,
+    /// Joints this layer contributes to.
+    pub mask: JointMask,
+    /// Weight of the whole layer, multiplied with the weights of [`Self::mask`].
+    pub weight: f32,
+    pub source: LayerSource,
+}
+
+impl AnimationLayer {
+    /// A layer with rotations set by a procedural system (see [`Self::set_rotation`]).
+    pub fn procedural(name: &str, blend: LayerBlend, mask: JointMask) -> Self {
+        Self {
+            name: name.to_string(),
+            blend,
+            mask,
+            weight: 1.0,
+            source: LayerSource::Procedural(HashMap::default()),
+        }
+    }
+
+    /// A layer playing a chunk of the motion data from the start.
+    pub fn clip(
+        name: &str,
+        blend: LayerBlend,
+        mask: JointMask,
+        chunk_index: usize,
+        looping: bool,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            blend,
+            mask,
+            weight: 1.0,
+            source: LayerSource::Clip {
+                motion_pose: MotionPose {
+                    chunk_index,
+                    time: 0.0,
+                },
+                looping,
+            },
+        }
+    }
+
+    /// Set the local rotation of a joint in a procedural layer.
+    ///
+    /// For [`LayerBlend::Additive`] this is the rotation applied on top of the joint.
+    /// Does nothing for clip layers.
+    pub fn set_rotation(&mut self, joint: &str, rotation: Quat) {
+        if let LayerSource::Procedural(rotations) = &mut self.source {
+            rotations.insert(joint.to_string(), rotation);
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LayerBlend {
+    /// Interpolate towards the layer rotations.
+    #[default]
+    Override,
+    /// Add the layer rotations on top of the existing ones.
+    Additive,
+}
+
+#[derive(Debug)]
+pub enum LayerSource {
+    /// Local joint rotations set by a procedural system.
+    Procedural(HashMap<String, Quat>),
+    /// A chunk of the motion data.
+    ///
+    /// Additive clips are relative to the first pose of the chunk.
+    Clip {
+        motion_pose: MotionPose,
+        looping: bool,
+    },
+}
+
+/// Per joint weights of an [`AnimationLayer`].
+#[derive(Debug, Clone, Default)]
+pub struct JointMask(HashMap<String, f32>);
+
+impl JointMask {
+    /// Fully weighted mask of the given joints.
+    pub fn from_joints<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+        Self(
+            names
+                .into_iter()
+                .map(|name| (name.to_string(), 1.0))
+                .collect(),
+        )
+    }
+
+    /// Fully weighted mask of `root` and all of its descendants (e.g. the upper body from the spine).
+    pub fn from_hierarchy(joints: &[JointInfo], root: &str) -> Self {
+        let mut included = Vec::with_capacity(joints.len());
+        for joint in joints {
+            // Parents always come before their children.
+            let is_included = joint.name() == root
+                || joint
+                    .parent_index()
+                    .and_then(|parent_index| included.get(parent_index).copied())
+                    .unwrap_or(false);
+            included.push(is_included);
+        }
+
+        Self::from_joints(
+            joints
+                .iter()
+                .zip(included)
+                .filter(|(_, is_included)| *is_included)
+                .map(|(joint, _)| joint.name()),
+        )
+    }
+
+    pub fn set_weight(&mut self, joint: &str, weight: f32) {
+        self.0.insert(joint.to_string(), weight);
+    }
+
+    /// Weight of a joint, 0 for joints outside of the mask.
+    pub fn weight(&self, joint: &str) -> f32 {
+        self.0.get(joint).copied().unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f32)> {
+        self.0.iter().map(|(name, weight)| (name.as_str(), *weight))
+    }
+}
@@ -0,0 +1,165 @@
+//! Named time markers inside chunks, notified when an animation plays past them.
+
+use std::ops::RangeBounds;
+
+use bevy::prelude::*;
+use bevy_bvh_anim::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::BVH_SCALE_RATIO;
+
+use super::chunk::ChunkIterator;
+use super::foot_ik::FootIkConfig;
+use super::motion_asset::MotionAsset;
+use super::motion_player::MotionPose;
+
+pub(super) struct AnimationNotifyPlugin;
+
+impl Plugin for AnimationNotifyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<AnimationNotify>();
+    }
+}
+
+/// Sent when the animation being faded into plays past an [`AnimationMarker`].
+///
+/// Animations fading out of a [`TrajectoryPoseStack`](super::motion_player::TrajectoryPoseStack)
+/// do not notify.
+#[derive(Event, Debug, Clone)]
+pub struct AnimationNotify {
+    pub entity: Entity,
+    /// Name of the [`AnimationMarker`].
+    pub name: String,
+    /// Chunk and time of the [`AnimationMarker`].
+    pub motion_pose: MotionPose,
+}
+
+/// A named time stamp inside a chunk.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AnimationMarker {
+    pub name: String,
+    /// Time in seconds from the start of the chunk.
+    pub time: f32,
+}
+
+/// [`AnimationMarker`]s of each chunk, sorted by time.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct AnimationMarkers(Vec<Vec<AnimationMarker>>);
+
+impl AnimationMarkers {
+    /// Push the markers of the next chunk.
+    pub fn push_chunk(&mut self, mut markers: Vec<AnimationMarker>) {
+        markers.sort_by(|a, b| f32::total_cmp(&a.time, &b.time));
+        self.0.push(markers);
+    }
+
+    /// Insert a marker into a chunk, keeping the chunk sorted.
+    pub fn insert(&mut self, chunk_index: usize, marker: AnimationMarker) {
+        if self.0.len() <= chunk_index {
+            self.0.resize_with(chunk_index + 1, Vec::new);
+        }
+
+        let markers = &mut self.0[chunk_index];
+        let index = markers.partition_point(|m| m.time <= marker.time);
+        markers.insert(index, marker);
+    }
+
+    /// Markers of a chunk, empty if the chunk has none.
+    pub fn get_chunk(&self, chunk_index: usize) -> &[AnimationMarker] {
+        self.0
+            .get(chunk_index)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Markers of a chunk with their time inside `range`.
+    pub fn in_range(
+        &self,
+        chunk_index: usize,
+        range: impl RangeBounds<f32>,
+    ) -> impl Iterator<Item = &AnimationMarker> {
+        self.get_chunk(chunk_index)
+            .iter()
+            .filter(move |marker| range.contains(&marker.time))
+    }
+
+    /// Send an [`AnimationNotify`] for every marker of a chunk inside `range`.
+    pub(super) fn notify(
+        &self,
+        entity: Entity,
+        chunk_index: usize,
+        range: impl RangeBounds<f32>,
+        notify_evw: &mut EventWriter<AnimationNotify>,
+    ) {
+        for marker in self.in_range(chunk_index, range) {
+            notify_evw.send(AnimationNotify {
+                entity,
+                name: marker.name.clone(),
+                motion_pose: MotionPose {
+                    chunk_index,
+                    time: marker.time,
+                },
+            });
+        }
+    }
+}
+
+/// Add a marker wherever a foot gets planted, named after the ankle joint of the leg.
+///
+/// Contacts are detected with the same thresholds as [`FootIkConfig`].
+pub fn derive_contact_markers(motion_asset: &mut MotionAsset, foot_ik_config: &FootIkConfig) {
+    let ankles = foot_ik_config
+        .legs
+        .iter()
+        .filter_map(|leg| {
+            let index = motion_asset
+                .joints()
+                .iter()
+                .position(|joint| joint.name() == leg.ankle)?;
+            Some((leg.ankle.clone(), index))
+        })
+        .collect::<Vec<_>>();
+
+    let pose_data = &motion_asset.pose_data;
+    let interval_time = pose_data.interval_time();
+    let mut joint_matrices = JointMatrices::new(motion_asset.joints());
+    let mut derived = Vec::new();
+
+    for (chunk_index, poses) in pose_data.iter_chunk().enumerate() {
+        let mut prev_positions = vec![None::<Vec3>; ankles.len()];
+        let mut prev_contacts = vec![false; ankles.len()];
+
+        for (frame_index, pose) in poses.iter().enumerate() {
+            joint_matrices.apply_frame(pose);
+
+            for (i, (name, joint_index)) in ankles.iter().enumerate() {
+                let (.., translation) =
+                    joint_matrices.world_matrices()[*joint_index].to_scale_rotation_translation();
+                let position = translation * BVH_SCALE_RATIO;
+
+                let speed = prev_positions[i]
+                    .map(|prev_position| position.distance(prev_position) / interval_time)
+                    .unwrap_or(f32::INFINITY);
+                let contact = speed < foot_ik_config.contact_velocity
+                    && position.y < foot_ik_config.contact_height;
+
+                if contact && !prev_contacts[i] {
+                    derived.push((
+                        chunk_index,
+                        AnimationMarker {
+                            name: name.clone(),
+                            time: frame_index as f32 * interval_time,
+                        },
+                    ));
+                }
+
+                prev_positions[i] = Some(position);
+                prev_contacts[i] = contact;
+            }
+        }
+    }
+
+    for (chunk_index, marker) in derived {
+        motion_asset.markers.insert(chunk_index, marker);
+    }
+}
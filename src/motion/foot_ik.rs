@@ -0,0 +1,458 @@
+//! Lock planted feet in world space and solve two-bone IK on the legs.
+
+use std::f32::consts::LN_2;
+
+use bevy::prelude::*;
+
+use crate::bvh_manager::bvh_player::JointMap;
+use crate::draw_axes::ColorPalette;
+use crate::transform2d::Transform2d;
+use crate::{GameMode, MotionUpdate};
+
+use super::motion_player::{MotionPlayerSet, MotionPose, TrajectoryPoseStack};
+
+pub(super) struct FootIkPlugin;
+
+impl Plugin for FootIkPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FootIkConfig>().add_systems(
+            MotionUpdate,
+            (
+                foot_ik.in_set(MotionPlayerSet::FootIk),
+                draw_foot_ik
+                    .after(MotionPlayerSet::FootIk)
+                    .run_if(|config: Res<FootIkConfig>| config.draw_gizmos),
+            )
+                .run_if(in_state(GameMode::Play)),
+        );
+    }
+}
+
+fn foot_ik(
+    mut q_players: Query<(
+        &mut FootIk,
+        &Transform2d,
+        &JointMap,
+        &TrajectoryPoseStack,
+        Entity,
+    )>,
+    q_parents: Query<&Parent>,
+    mut q_transforms: Query<&mut Transform>,
+    config: Res<FootIkConfig>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+
+    for (mut foot_ik, transform2d, joint_map, traj_pose_stack, entity) in q_players.iter_mut() {
+        if !foot_ik.enabled {
+            foot_ik.legs.clear();
+            continue;
+        }
+
+        // The entity transform is only synced with its [`Transform2d`] in PostUpdate.
+        let Ok(mut root_transform) = q_transforms.get(entity).copied() else {
+            continue;
+        };
+        root_transform.translation.x = transform2d.translation.x;
+        root_transform.translation.z = transform2d.translation.y;
+        root_transform.rotation = Quat::from_rotation_y(transform2d.angle);
+        let root_matrix = root_transform.compute_matrix();
+
+        let motion_pose = traj_pose_stack
+            .target()
+            .map(|traj_pose| *traj_pose.motion_pose());
+
+        let foot_ik = &mut *foot_ik;
+        foot_ik.legs.resize(config.legs.len(), default());
+
+        for (leg_config, leg) in config.legs.iter().zip(foot_ik.legs.iter_mut()) {
+            let (Some(&hip), Some(&knee), Some(&ankle)) = (
+                joint_map.get(&leg_config.hip),
+                joint_map.get(&leg_config.knee),
+                joint_map.get(&leg_config.ankle),
+            ) else {
+                continue;
+            };
+
+            let world_matrix = |joint| {
+                relative_matrix(joint, entity, &q_parents, &q_transforms)
+                    .map(|matrix| root_matrix * matrix)
+            };
+            let (Some(hip_matrix), Some(knee_matrix), Some(ankle_matrix)) =
+                (world_matrix(hip), world_matrix(knee), world_matrix(ankle))
+            else {
+                continue;
+            };
+
+            let (_, hip_global_rot, hip_pos) = hip_matrix.to_scale_rotation_translation();
+            let (_, knee_global_rot, knee_pos) = knee_matrix.to_scale_rotation_translation();
+            let (_, ankle_global_rot, ankle_pos) = ankle_matrix.to_scale_rotation_translation();
+
+            // Detect contacts from the animated (pre-IK) foot.
+            let contact = motion_pose
+                .and_then(|motion_pose| leg_config.annotated_contact(&motion_pose))
+                .unwrap_or_else(|| {
+                    let speed = match (leg.prev_position, dt > 0.0) {
+                        (Some(prev_position), true) => ankle_pos.distance(prev_position) / dt,
+                        _ => f32::INFINITY,
+                    };
+                    // Height above the ground the animation was authored on.
+                    let height = ankle_pos.y - root_transform.translation.y + foot_ik.pelvis_offset;
+                    speed < config.contact_velocity && height < config.contact_height
+                });
+            leg.prev_position = Some(ankle_pos);
+            leg.contact = contact;
+
+            // Animated foot placed onto the ground below it.
+            let placed_pos = ankle_pos + Vec3::Y * (leg.ground_offset - foot_ik.pelvis_offset);
+
+            let mut target = placed_pos;
+            if foot_ik.foot_locking {
+                match (contact, leg.lock) {
+                    (true, Some(lock)) if lock.distance(placed_pos) > config.unlock_distance => {
+                        // Stretched too far, let go of the foot.
+                        leg.release(placed_pos);
+                    }
+                    (true, Some(lock)) => target = lock,
+                    // Wait for a released foot to settle before locking it again.
+                    (true, None) if leg.release_offset.length() < config.unlock_distance * 0.5 => {
+                        // Lock where the foot currently is to avoid popping.
+                        let lock = placed_pos + leg.release_offset;
+                        leg.lock = Some(lock);
+                        leg.release_offset = Vec3::ZERO;
+                        target = lock;
+                    }
+                    (true, None) => {}
+                    (false, Some(_)) => leg.release(placed_pos),
+                    (false, None) => {}
+                }
+
+                if leg.lock.is_none() {
+                    // Blend out of the previous lock.
+                    leg.release_offset *=
+                        f32::exp(-LN_2 * dt / f32::max(config.release_halflife, f32::EPSILON));
+                    target += leg.release_offset;
+                }
+            } else {
+                leg.lock = None;
+                leg.release_offset = Vec3::ZERO;
+            }
+            leg.animated = ankle_pos;
+            leg.target = target;
+
+            if target.distance_squared(ankle_pos) < f32::EPSILON {
+                continue;
+            }
+
+            let (Ok(hip_transform), Ok(knee_transform)) =
+                (q_transforms.get(hip), q_transforms.get(knee))
+            else {
+                continue;
+            };
+
+            let (hip_local_rot, knee_local_rot) = two_bone_ik(
+                [hip_pos, knee_pos, ankle_pos],
+                target,
+                [hip_global_rot, knee_global_rot],
+                [hip_transform.rotation, knee_transform.rotation],
+            );
+
+            // Keep the world orientation of the foot.
+            let hip_parent_rot = hip_global_rot * hip_transform.rotation.inverse();
+            let knee_global_rot = hip_parent_rot * hip_local_rot * knee_local_rot;
+            let ankle_local_rot = knee_global_rot.inverse() * ankle_global_rot;
+
+            for (joint, rotation) in [
+                (hip, hip_local_rot),
+                (knee, knee_local_rot),
+                (ankle, ankle_local_rot),
+            ] {
+                if let Ok(mut transform) = q_transforms.get_mut(joint) {
+                    transform.rotation = rotation;
+                }
+            }
+        }
+    }
+}
+
+fn draw_foot_ik(q_foot_iks: Query<&FootIk>, mut gizmos: Gizmos, palette: Res<ColorPalette>) {
+    for foot_ik in q_foot_iks.iter() {
+        for leg in foot_ik.legs.iter() {
+            let color = match (leg.lock.is_some(), leg.contact) {
+                (true, _) => palette.red,
+                (false, true) => palette.orange,
+                (false, false) => palette.green,
+            };
+
+            gizmos.sphere(Isometry3d::from_translation(leg.target), 0.04, color);
+            gizmos.line(leg.animated, leg.target, palette.base4);
+        }
+    }
+}
+
+/// Matrix of `joint` relative to its ancestor `root`.
+fn relative_matrix(
+    joint: Entity,
+    root: Entity,
+    q_parents: &Query<&Parent>,
+    q_transforms: &Query<&mut Transform>,
+) -> Option<Mat4> {
+    let mut matrix = q_transforms.get(joint).ok()?.compute_matrix();
+    let mut entity = q_parents.get(joint).ok()?.get();
+
+    while entity != root {
+        matrix = q_transforms.get(entity).ok()?.compute_matrix() * matrix;
+        entity = q_parents.get(entity).ok()?.get();
+    }
+
+    Some(matrix)
+}
+
+/// Analytical two-bone IK bringing the end of the chain `[a, b, c]` to `target`.
+///
+/// Returns the new local rotations of `a` and `b`.
+fn two_bone_ik(
+    [a, b, c]: [Vec3; 3],
+    target: Vec3,
+    [a_global_rot, b_global_rot]: [Quat; 2],
+    [a_local_rot, b_local_rot]: [Quat; 2],
+) -> (Quat, Quat) {
+    const EPSILON: f32 = 0.001;
+
+    let angle_between = |lhs: Vec3, rhs: Vec3| {
+        f32::acos(f32::clamp(
+            Vec3::dot(lhs.normalize_or_zero(), rhs.normalize_or_zero()),
+            -1.0,
+            1.0,
+        ))
+    };
+
+    let length_ab = a.distance(b);
+    let length_cb = c.distance(b);
+    let length_at = f32::clamp(a.distance(target), EPSILON, length_ab + length_cb - EPSILON);
+
+    // Current angles.
+    let ac_ab_0 = angle_between(c - a, b - a);
+    let ba_bc_0 = angle_between(a - b, c - b);
+    let ac_at_0 = angle_between(c - a, target - a);
+
+    // Desired angles (law of cosines).
+    let ac_ab_1 = f32::acos(f32::clamp(
+        (length_cb * length_cb - length_ab * length_ab - length_at * length_at)
+            / (-2.0 * length_ab * length_at),
+        -1.0,
+        1.0,
+    ));
+    let ba_bc_1 = f32::acos(f32::clamp(
+        (length_at * length_at - length_ab * length_ab - length_cb * length_cb)
+            / (-2.0 * length_ab * length_cb),
+        -1.0,
+        1.0,
+    ));
+
+    // Bend around the current plane of the chain.
+    let bend_axis = Vec3::cross(c - a, b - a).normalize_or_zero();
+    let target_axis = Vec3::cross(c - a, target - a).normalize_or_zero();
+
+    let r0 = Quat::from_axis_angle(a_global_rot.inverse() * bend_axis, ac_ab_1 - ac_ab_0);
+    let r1 = Quat::from_axis_angle(b_global_rot.inverse() * bend_axis, ba_bc_1 - ba_bc_0);
+    let r2 = match target_axis == Vec3::ZERO {
+        true => Quat::IDENTITY,
+        false => Quat::from_axis_angle(a_global_rot.inverse() * target_axis, ac_at_0),
+    };
+
+    // Bend the chain first, then swing it towards the target.
+    (a_local_rot * r2 * r0, b_local_rot * r1)
+}
+
+/// Per character foot IK settings and state.
+///
+/// Disabled by default, enable it once [`FootIkConfig::legs`] matches the skeleton.
+#[derive(Component, Debug)]
+pub struct FootIk {
+    /// Solve IK on the legs of this character.
+    pub enabled: bool,
+    /// Lock planted feet in world space.
+    pub foot_locking: bool,
+    /// State of each leg in [`FootIkConfig::legs`].
+    legs: Vec<LegState>,
+    /// How much the pelvis has been lowered to adapt to the ground.
+    pelvis_offset: f32,
+}
+
+impl FootIk {
+    /// Foot positions solved for in the last update.
+    pub fn leg_targets(&self) -> impl Iterator<Item = Vec3> + '_ {
+        self.legs.iter().map(|leg| leg.target)
+    }
+
+    /// Set the ground height below each foot (relative to the ground below the root)
+    /// and how much the pelvis has been lowered.
+    pub fn set_ground_offsets(&mut self, pelvis_offset: f32, ground_offsets: &[f32]) {
+        self.pelvis_offset = pelvis_offset;
+        for (leg, &ground_offset) in self.legs.iter_mut().zip(ground_offsets) {
+            leg.ground_offset = ground_offset;
+        }
+    }
+}
+
+impl Default for FootIk {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            foot_locking: false,
+            legs: Vec::new(),
+            pelvis_offset: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct LegState {
+    /// Animated foot position of the previous frame.
+    prev_position: Option<Vec3>,
+    /// Is the foot planted?
+    contact: bool,
+    /// World position the foot is locked to.
+    lock: Option<Vec3>,
+    /// Offset from the animated foot when the lock was released.
+    release_offset: Vec3,
+    /// Animated foot position (pre-IK).
+    animated: Vec3,
+    /// Foot position the IK solves for.
+    target: Vec3,
+    /// Height of the ground below the foot relative to the ground below the root.
+    ground_offset: f32,
+}
+
+impl LegState {
+    fn release(&mut self, animated: Vec3) {
+        if let Some(lock) = self.lock.take() {
+            self.release_offset = lock - animated;
+        }
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct FootIkConfig {
+    /// Joint chains of the legs, named after the joints of the skeleton being animated.
+    pub legs: Vec<LegConfig>,
+    /// Maximum speed of the foot to be detected as planted.
+    pub contact_velocity: f32,
+    /// Maximum height of the foot to be detected as planted.
+    pub contact_height: f32,
+    /// Release the lock once the animated foot is further than this.
+    pub unlock_distance: f32,
+    /// Halflife of blending back to the animated foot after a lock is released.
+    pub release_halflife: f32,
+    pub draw_gizmos: bool,
+}
+
+impl Default for FootIkConfig {
+    fn default() -> Self {
+        Self {
+            legs: Vec::new(),
+            contact_velocity: 0.3,
+            contact_height: 0.15,
+            unlock_distance: 0.2,
+            release_halflife: 0.05,
+            draw_gizmos: false,
+        }
+    }
+}
+
+/// Joint names of a leg.
+#[derive(Debug, Clone)]
+pub struct LegConfig {
+    pub hip: String,
+    pub knee: String,
+    pub ankle: String,
+    /// Annotated contacts, used instead of detection for the annotated chunks.
+    pub contacts: Vec<ContactAnnotation>,
+}
+
+impl LegConfig {
+    pub fn new(hip: &str, knee: &str, ankle: &str) -> Self {
+        Self {
+            hip: hip.to_string(),
+            knee: knee.to_string(),
+            ankle: ankle.to_string(),
+            contacts: Vec::new(),
+        }
+    }
+
+    /// Is the foot planted at `motion_pose`?
+    ///
+    /// Returns [`None`] if the chunk is not annotated.
+    pub fn annotated_contact(&self, motion_pose: &MotionPose) -> Option<bool> {
+        let mut annotations = self
+            .contacts
+            .iter()
+            .filter(|contact| contact.chunk_index == motion_pose.chunk_index)
+            .peekable();
+
+        annotations.peek()?;
+        Some(
+            annotations
+                .any(|contact| (contact.start_time..=contact.end_time).contains(&motion_pose.time)),
+        )
+    }
+}
+
+/// Time range inside a chunk where the foot is planted.
+#[derive(Debug, Clone, Copy)]
+pub struct ContactAnnotation {
+    pub chunk_index: usize,
+    pub start_time: f32,
+    pub end_time: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// End of the chain `[a, b, c]` after applying the local rotations of [`two_bone_ik`].
+    ///
+    /// All joints start with identity rotations.
+    fn solved_end([a, b, c]: [Vec3; 3], target: Vec3) -> Vec3 {
+        let (a_rot, b_rot) =
+            two_bone_ik([a, b, c], target, [Quat::IDENTITY; 2], [Quat::IDENTITY; 2]);
+
+        let solved_b = a + a_rot * (b - a);
+        solved_b + a_rot * b_rot * (c - b)
+    }
+
+    #[test]
+    fn two_bone_ik_reaches_target() {
+        let chain = [
+            Vec3::ZERO,
+            Vec3::new(0.0, -1.0, 0.1),
+            Vec3::new(0.0, -2.0, 0.0),
+        ];
+
+        for target in [
+            Vec3::new(0.0, -1.5, 0.3),
+            Vec3::new(0.4, -1.2, 0.2),
+            Vec3::new(-0.3, -1.8, -0.2),
+        ] {
+            let end = solved_end(chain, target);
+            assert!(end.distance(target) < 1e-3, "{end} != {target}");
+        }
+    }
+
+    #[test]
+    fn two_bone_ik_stretches_towards_unreachable_target() {
+        let chain = [
+            Vec3::ZERO,
+            Vec3::new(0.0, -1.0, 0.1),
+            Vec3::new(0.0, -2.0, 0.0),
+        ];
+        let target = Vec3::new(1.0, -3.0, 0.5);
+
+        let end = solved_end(chain, target);
+        let reach = chain[0].distance(chain[1]) + chain[1].distance(chain[2]);
+
+        assert!(end.normalize().distance(target.normalize()) < 1e-3);
+        assert!((end.length() - reach).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,256 @@
+//! Output the motion matched pose into a Bevy [`AnimationGraph`].
+
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use bevy::animation::{animated_field, AnimationTarget, AnimationTargetId};
+use bevy::app::Animation;
+use bevy::math::curve::{Curve, Interval};
+use bevy::prelude::*;
+
+use crate::bvh_manager::bvh_player::JointMap;
+use crate::timestep::interpolate_fixed_step;
+use crate::GameMode;
+
+use super::MotionData;
+
+pub(super) struct GraphOutputPlugin;
+
+impl Plugin for GraphOutputPlugin {
+    fn build(&self, app: &mut App) {
+        // Runs after the fixed step interpolation, so that the graph blends the rendered pose.
+        app.add_systems(
+            PostUpdate,
+            (build_graph_output_clips, write_graph_output)
+                .chain()
+                .after(interpolate_fixed_step)
+                .before(Animation)
+                .run_if(in_state(GameMode::Play)),
+        );
+    }
+}
+
+/// Duration of the clip holding the motion matched pose.
+///
+/// The curves are constant, so this only affects how often the clip repeats.
+const OUTPUT_CLIP_DURATION: f32 = 1.0;
+
+/// Fill the output clips with curves sampling the [`SharedPose`] of their motion players.
+///
+/// Only done once per [`MotionGraphOutput`], the pose itself is updated by [`write_graph_output`].
+fn build_graph_output_clips(
+    mut commands: Commands,
+    motion_data: MotionData,
+    mut q_outputs: Query<(&mut MotionGraphOutput, &JointMap, Entity)>,
+    q_targets: Query<Option<&AnimationTarget>>,
+    q_names: Query<&Name>,
+    q_parents: Query<&Parent>,
+    mut clips: ResMut<Assets<AnimationClip>>,
+) {
+    let Some(motion_asset) = motion_data.get() else {
+        return;
+    };
+
+    for (mut output, joint_map, entity) in q_outputs.iter_mut() {
+        if output.clip_built {
+            continue;
+        }
+
+        let Some(clip) = clips.get_mut(&output.clip) else {
+            continue;
+        };
+
+        let mut output_clip = AnimationClip::default();
+        output_clip.set_duration(OUTPUT_CLIP_DURATION);
+
+        for (joint_index, joint) in motion_asset.joints().iter().enumerate() {
+            let Some((&joint_entity, Ok(target))) = joint_map
+                .get(joint.name())
+                .map(|joint_entity| (joint_entity, q_targets.get(*joint_entity)))
+            else {
+                continue;
+            };
+
+            let target_id = match target {
+                Some(target) => target.id,
+                None => {
+                    let Some(target_id) =
+                        target_id_from_path(joint_entity, entity, &q_names, &q_parents)
+                    else {
+                        continue;
+                    };
+                    commands.entity(joint_entity).insert(AnimationTarget {
+                        id: target_id,
+                        player: entity,
+                    });
+                    target_id
+                }
+            };
+
+            let curve = SharedJointCurve {
+                pose: output.pose.clone(),
+                joint_index,
+            };
+            output_clip.add_curve_to_target(
+                target_id,
+                AnimatableCurve::new(animated_field!(Transform::translation), curve.clone()),
+            );
+            output_clip.add_curve_to_target(
+                target_id,
+                AnimatableCurve::new(animated_field!(Transform::rotation), curve),
+            );
+        }
+
+        *clip = output_clip;
+        output.clip_built = true;
+    }
+}
+
+/// Copy the final joint transforms of the motion players into their [`SharedPose`].
+fn write_graph_output(
+    motion_data: MotionData,
+    q_outputs: Query<(&MotionGraphOutput, &JointMap)>,
+    q_transforms: Query<&Transform>,
+) {
+    let Some(motion_asset) = motion_data.get() else {
+        return;
+    };
+
+    for (output, joint_map) in q_outputs.iter() {
+        let mut pose = output.pose.write();
+        pose.resize(motion_asset.joints().len(), (Vec3::ZERO, Quat::IDENTITY));
+
+        for (joint_index, joint) in motion_asset.joints().iter().enumerate() {
+            if let Some(transform) = joint_map
+                .get(joint.name())
+                .and_then(|joint_entity| q_transforms.get(*joint_entity).ok())
+            {
+                pose[joint_index] = (transform.translation, transform.rotation);
+            }
+        }
+    }
+}
+
+/// [`AnimationTargetId`] from the names of the entities between `player` (exclusive) and `joint`.
+fn target_id_from_path(
+    joint: Entity,
+    player: Entity,
+    q_names: &Query<&Name>,
+    q_parents: &Query<&Parent>,
+) -> Option<AnimationTargetId> {
+    let mut names = Vec::new();
+    let mut entity = joint;
+
+    while entity != player {
+        names.push(q_names.get(entity).ok()?.clone());
+        entity = q_parents.get(entity).ok()?.get();
+    }
+
+    Some(AnimationTargetId::from_names(names.iter().rev()))
+}
+
+/// Exposes the final pose of a motion player as a clip node inside an [`AnimationGraph`],
+/// so it can be blended with authored clips.
+///
+/// The entity also needs an [`AnimationPlayer`] playing [`Self::node`] on repeat.
+/// The motion player still poses the joints every step, the animation graph then
+/// overwrites them with the blended result before they are rendered.
+#[derive(Component, Debug, Clone)]
+pub struct MotionGraphOutput {
+    /// Clip with curves sampling [`Self::pose`].
+    clip: Handle<AnimationClip>,
+    /// Node of [`Self::clip`] inside the graph.
+    node: AnimationNodeIndex,
+    /// Final pose of the motion player.
+    pose: SharedPose,
+    /// Have the curves of [`Self::clip`] been added?
+    clip_built: bool,
+}
+
+impl MotionGraphOutput {
+    /// Add a clip node holding the motion matched pose to `graph` under `parent`.
+    pub fn add_to_graph(
+        graph: &mut AnimationGraph,
+        clips: &mut Assets<AnimationClip>,
+        parent: AnimationNodeIndex,
+        weight: f32,
+    ) -> Self {
+        let clip = clips.add(AnimationClip::default());
+        let node = graph.add_clip(clip.clone(), weight, parent);
+
+        Self {
+            clip,
+            node,
+            pose: SharedPose::default(),
+            clip_built: false,
+        }
+    }
+}
+
+// Getters
+impl MotionGraphOutput {
+    pub fn clip(&self) -> &Handle<AnimationClip> {
+        &self.clip
+    }
+
+    pub fn node(&self) -> AnimationNodeIndex {
+        self.node
+    }
+
+    pub fn pose(&self) -> &SharedPose {
+        &self.pose
+    }
+}
+
+/// Local translation and rotation of each joint (indexed like [`MotionAsset::joints`]),
+/// shared between a [`MotionGraphOutput`] and the curves of its clip.
+///
+/// [`MotionAsset::joints`]: super::motion_asset::MotionAsset::joints
+#[derive(Reflect, Default, Debug, Clone)]
+#[reflect(opaque)]
+pub struct SharedPose(Arc<RwLock<Vec<(Vec3, Quat)>>>);
+
+impl SharedPose {
+    pub fn read(&self) -> RwLockReadGuard<'_, Vec<(Vec3, Quat)>> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, Vec<(Vec3, Quat)>> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Translation and rotation of the joint at `joint_index`, identity if it is not written yet.
+    pub fn get(&self, joint_index: usize) -> (Vec3, Quat) {
+        self.read()
+            .get(joint_index)
+            .copied()
+            .unwrap_or((Vec3::ZERO, Quat::IDENTITY))
+    }
+}
+
+/// Curve sampling a joint of a [`SharedPose`], constant over time.
+#[derive(Reflect, Debug, Clone)]
+#[reflect(opaque)]
+struct SharedJointCurve {
+    pose: SharedPose,
+    joint_index: usize,
+}
+
+impl Curve<Vec3> for SharedJointCurve {
+    fn domain(&self) -> Interval {
+        Interval::EVERYWHERE
+    }
+
+    fn sample_unchecked(&self, _t: f32) -> Vec3 {
+        self.pose.get(self.joint_index).0
+    }
+}
+
+impl Curve<Quat> for SharedJointCurve {
+    fn domain(&self) -> Interval {
+        Interval::EVERYWHERE
+    }
+
+    fn sample_unchecked(&self, _t: f32) -> Quat {
+        self.pose.get(self.joint_index).1
+    }
+}
@@ -0,0 +1,192 @@
+//! Inertialization blending: decay the offset between the previous and the new animation
+//! instead of cross-fading between both of them.
+
+use std::f32::consts::LN_2;
+
+use bevy::prelude::*;
+
+use crate::bvh_manager::bvh_player::JointMap;
+use crate::{GameMode, MotionUpdate};
+
+use super::motion_player::{BlendMode, MotionPlayerConfig, MotionPlayerSet, TrajectoryPoseStack};
+use super::MotionData;
+
+pub(super) struct InertializationPlugin;
+
+impl Plugin for InertializationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            MotionUpdate,
+            inertialize
+                .in_set(MotionPlayerSet::Inertialize)
+                .run_if(in_state(GameMode::Play)),
+        );
+    }
+}
+
+/// Add the decaying offsets on top of the joint transforms of the new animation.
+fn inertialize(
+    motion_data: MotionData,
+    mut q_motion_players: Query<(&mut Inertialization, &TrajectoryPoseStack, &JointMap)>,
+    mut q_transforms: Query<&mut Transform>,
+    motion_player_config: Res<MotionPlayerConfig>,
+    time: Res<Time>,
+) {
+    let Some(motion_asset) = motion_data.get() else {
+        return;
+    };
+
+    let pose_data = &motion_asset.pose_data;
+    let joints = motion_asset.joints();
+
+    let dt = time.delta_secs();
+    let halflife = motion_player_config.interp_duration() * INTERP_DURATION_HALFLIFE_RATIO;
+
+    for (mut inertialization, traj_pose_stack, joint_map) in q_motion_players.iter_mut() {
+        let inertialization = &mut *inertialization;
+        inertialization.joints.resize(joints.len(), default());
+
+        // There is no previous output to blend from on the very first animation.
+        let capture = inertialization.pending_switch
+            && inertialization.has_output
+            && motion_player_config.blend_mode == BlendMode::Inertialization;
+
+        // Sample the new animation slightly ahead to estimate its velocities.
+        let next_pose = match capture {
+            true => traj_pose_stack.target().and_then(|traj_pose| {
+                let mut motion_pose = *traj_pose.motion_pose();
+                motion_pose.time += pose_data.interval_time();
+                motion_pose.get_pose(pose_data, joints)
+            }),
+            false => None,
+        };
+
+        for (joint_index, joint_info) in joints.iter().enumerate() {
+            let Some(mut transform) = joint_map
+                .get(joint_info.name())
+                .and_then(|entity| q_transforms.get_mut(*entity).ok())
+            else {
+                continue;
+            };
+
+            let joint = &mut inertialization.joints[joint_index];
+
+            if capture {
+                // Velocity of the new animation (root joint is driven by the root motion).
+                let (target_vel, target_angular_vel) = match (&next_pose, joint_index) {
+                    (Some(next_pose), 1..) => {
+                        let (next_pos, next_rot) = next_pose.get_pos_rot(joint_index);
+                        let interval_time = pose_data.interval_time();
+                        (
+                            (joint_info.offset() + next_pos - transform.translation)
+                                / interval_time,
+                            shortest_scaled_axis(next_rot * transform.rotation.inverse())
+                                / interval_time,
+                        )
+                    }
+                    _ => (Vec3::ZERO, Vec3::ZERO),
+                };
+
+                // Offset from the new animation to the previous output.
+                joint.offset_pos = joint.prev_pos - transform.translation;
+                joint.offset_vel = joint.vel - target_vel;
+                joint.offset_rot =
+                    shortest_scaled_axis(joint.prev_rot * transform.rotation.inverse());
+                joint.offset_angular_vel = joint.angular_vel - target_angular_vel;
+            } else {
+                decay_spring(&mut joint.offset_pos, &mut joint.offset_vel, halflife, dt);
+                decay_spring(
+                    &mut joint.offset_rot,
+                    &mut joint.offset_angular_vel,
+                    halflife,
+                    dt,
+                );
+            }
+
+            transform.translation += joint.offset_pos;
+            transform.rotation = Quat::from_scaled_axis(joint.offset_rot) * transform.rotation;
+
+            // Record the output for the next switch.
+            if dt > 0.0 {
+                joint.vel = (transform.translation - joint.prev_pos) / dt;
+                joint.angular_vel =
+                    shortest_scaled_axis(transform.rotation * joint.prev_rot.inverse()) / dt;
+            }
+            joint.prev_pos = transform.translation;
+            joint.prev_rot = transform.rotation;
+        }
+
+        inertialization.pending_switch = false;
+        inertialization.has_output = true;
+    }
+}
+
+/// Halflife of the offset decay relative to [`MotionPlayerConfig::interp_duration`].
+///
+/// The offset is below 10% of its initial value after the interpolation duration.
+const INTERP_DURATION_HALFLIFE_RATIO: f32 = 0.25;
+
+/// Decay `x` towards zero using an exact critically damped spring.
+fn decay_spring(x: &mut Vec3, v: &mut Vec3, halflife: f32, dt: f32) {
+    let y = 2.0 * LN_2 / f32::max(halflife, f32::EPSILON);
+    let j1 = *v + *x * y;
+    let eydt = f32::exp(-y * dt);
+
+    *x = eydt * (*x + j1 * dt);
+    *v = eydt * (*v - j1 * y * dt);
+}
+
+/// Scaled axis of the shortest rotation.
+fn shortest_scaled_axis(rotation: Quat) -> Vec3 {
+    match rotation.w < 0.0 {
+        true => (-rotation).to_scaled_axis(),
+        false => rotation.to_scaled_axis(),
+    }
+}
+
+/// Decaying joint offsets of a [`TrajectoryPoseStack`] in [`BlendMode::Inertialization`].
+#[derive(Component, Default, Debug)]
+pub struct Inertialization {
+    joints: Vec<InertialJoint>,
+    /// A new animation has been jumped to in this frame.
+    pending_switch: bool,
+    /// Has [`Self::joints`] recorded an output yet?
+    has_output: bool,
+}
+
+impl Inertialization {
+    /// Capture the offsets from the current output on the next update.
+    pub fn switch(&mut self) {
+        self.pending_switch = true;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct InertialJoint {
+    offset_pos: Vec3,
+    offset_vel: Vec3,
+    /// Rotation offset in scaled axis.
+    offset_rot: Vec3,
+    offset_angular_vel: Vec3,
+    /// Output of the previous frame.
+    prev_pos: Vec3,
+    prev_rot: Quat,
+    /// Output velocities of the previous frame.
+    vel: Vec3,
+    angular_vel: Vec3,
+}
+
+impl Default for InertialJoint {
+    fn default() -> Self {
+        Self {
+            offset_pos: Vec3::ZERO,
+            offset_vel: Vec3::ZERO,
+            offset_rot: Vec3::ZERO,
+            offset_angular_vel: Vec3::ZERO,
+            prev_pos: Vec3::ZERO,
+            prev_rot: Quat::IDENTITY,
+            vel: Vec3::ZERO,
+            angular_vel: Vec3::ZERO,
+        }
+    }
+}
@@ -9,6 +9,7 @@ use thiserror::Error;
 
 use crate::LARGE_EPSILON;
 
+use super::animation_notify::{AnimationMarker, AnimationMarkers};
 use super::joint_info::JointInfo;
 use super::pose_data::PoseData;
 use super::trajectory_data::{TrajectoryData, TrajectoryDataConfig, TrajectoryDataPoint};
@@ -31,6 +32,9 @@ pub struct MotionAsset {
     pub trajectory_data: TrajectoryData,
     /// Pose data for pose matching and animation sampling.
     pub pose_data: PoseData,
+    /// Named time markers of each chunk.
+    #[serde(default)]
+    pub markers: AnimationMarkers,
     pub animation_file: Vec<String>,
 }
 
@@ -43,17 +47,22 @@ impl MotionAsset {
                 .collect(),
             trajectory_data: TrajectoryData::new(config),
             pose_data: PoseData::new(bvh.frame_time().as_secs_f32()),
+            markers: AnimationMarkers::default(),
             animation_file: Vec::new(),
         }
     }
 
-    pub fn append_bvhs<'a>(&mut self, bvhs: impl Iterator<Item = &'a BvhAsset>) {
+    /// Append each bvh as a chunk along with its [`AnimationMarker`]s.
+    pub fn append_bvhs<'a>(
+        &mut self,
+        bvhs: impl Iterator<Item = (&'a BvhAsset, &'a [AnimationMarker])>,
+    ) {
         let traj_config = *self.trajectory_data.config();
         let pose_interval = self.pose_data.interval_time();
 
         let mut trajectory_chunk = Vec::<TrajectoryDataPoint>::new();
 
-        for bvh in bvhs {
+        for (bvh, markers) in bvhs {
             let name = bvh.name();
             info!("Building {}...", name);
 
@@ -166,12 +175,46 @@ impl MotionAsset {
             self.trajectory_data
                 .append_trajectory_chunk(&mut trajectory_chunk);
             self.pose_data.append_frames(bvh);
+            self.markers.push_chunk(markers.to_vec());
         }
         println!("Bvh File Names: {:?}", self.animation_file);
         println!("Bvh File Len: {}", self.animation_file.len());
     }
 }
 
+#[cfg(test)]
+impl MotionAsset {
+    /// Build a [`MotionAsset`] without joints from raw trajectory chunks.
+    ///
+    /// Each chunk gets an empty pose per trajectory point, so poses and trajectories share
+    /// the same interval time.
+    pub(crate) fn from_trajectory_chunks(
+        config: TrajectoryDataConfig,
+        chunks: impl IntoIterator<Item = (Vec<TrajectoryDataPoint>, bool)>,
+    ) -> Self {
+        use super::pose_data::Pose;
+
+        let mut motion_asset = Self {
+            joints: Vec::new(),
+            trajectory_data: TrajectoryData::new(config),
+            pose_data: PoseData::new(config.interval_time),
+            markers: AnimationMarkers::default(),
+            animation_file: Vec::new(),
+        };
+
+        for (mut trajectory_chunk, loopable) in chunks {
+            let poses = vec![Pose(Vec::new()); trajectory_chunk.len()];
+            motion_asset
+                .trajectory_data
+                .append_trajectory_chunk(&mut trajectory_chunk);
+            motion_asset.pose_data.append_poses(poses, loopable);
+            motion_asset.markers.push_chunk(Vec::new());
+        }
+
+        motion_asset
+    }
+}
+
 impl MotionAsset {
     pub fn joints(&self) -> &[JointInfo] {
         &self.joints
@@ -1,13 +1,23 @@
 //! Play motion data based on events and resources.
 
+use std::ops::Bound;
+
 use bevy::prelude::*;
 
+use crate::trajectory::{Trajectory, TrajectoryConfig};
 use crate::transform2d::Transform2d;
 use crate::{bvh_manager::bvh_player::JointMap, GameMode};
-use crate::{MainSet, BVH_SCALE_RATIO, LARGE_EPSILON};
+use crate::{MainSet, MotionUpdate, BVH_SCALE_RATIO, LARGE_EPSILON};
 
+use super::animation_layers::AnimationLayers;
+use super::animation_notify::AnimationNotify;
 use super::chunk::ChunkIterator;
-use super::pose_data::{Pose, PoseData};
+use super::foot_ik::FootIk;
+use super::inertialization::Inertialization;
+use super::joint_info::JointInfo;
+use super::motion_asset::MotionAsset;
+use super::pose_data::{JointPose, PoseData};
+use super::simulation_bone::SimulationBone;
 use super::MotionData;
 
 pub(super) struct MotionPlayerPlugin;
@@ -15,7 +25,7 @@ pub(super) struct MotionPlayerPlugin;
 impl Plugin for MotionPlayerPlugin {
     fn build(&self, app: &mut App) {
         app.configure_sets(
-            Update,
+            MotionUpdate,
             (
                 MotionPlayerSet::JumpToPose,
                 MotionPlayerSet::ApplyPose,
@@ -23,6 +33,9 @@ impl Plugin for MotionPlayerPlugin {
                     MotionPlayerSet::ApplyJointTransform,
                     MotionPlayerSet::ApplyRootTransform,
                 ),
+                MotionPlayerSet::ApplyLayers,
+                MotionPlayerSet::Inertialize,
+                MotionPlayerSet::FootIk,
                 MotionPlayerSet::Interpolate,
             )
                 .chain()
@@ -31,10 +44,13 @@ impl Plugin for MotionPlayerPlugin {
 
         app.insert_resource(MotionPlayerConfig {
             interp_duration: 0.3333,
+            blend_mode: BlendMode::CrossFade,
+            min_playback_rate: 0.8,
+            max_playback_rate: 1.25,
         })
         .add_event::<JumpToPose>()
         .add_systems(
-            Update,
+            MotionUpdate,
             (
                 jump_to_pose.in_set(MotionPlayerSet::JumpToPose),
                 apply_trajectory_pose
@@ -43,8 +59,13 @@ impl Plugin for MotionPlayerPlugin {
                 pose_to_joint_transforms.in_set(MotionPlayerSet::ApplyJointTransform),
                 apply_root_transform.in_set(MotionPlayerSet::ApplyRootTransform),
                 (
-                    (loop_trajectory_pose_time, update_trajectory_pose_time).chain(),
-                    update_interp_factor,
+                    (
+                        update_playback_rate,
+                        loop_trajectory_pose_time,
+                        update_trajectory_pose_time,
+                    )
+                        .chain(),
+                    update_blend_weights,
                 )
                     .in_set(MotionPlayerSet::Interpolate),
                 // _test.before(MotionPlayerSet::JumpToPose),
@@ -58,7 +79,7 @@ impl Plugin for MotionPlayerPlugin {
 // fn init_pose() {}
 
 fn _test(
-    // q_entities: Query<Entity, With<TrajectoryPosePair>>,
+    // q_entities: Query<Entity, With<TrajectoryPoseStack>>,
     // mut jump_evw: EventWriter<JumpToPose>,
     input: Res<ButtonInput<KeyCode>>,
     mut time: ResMut<Time<Virtual>>,
@@ -86,19 +107,14 @@ fn _test(
 
 fn apply_root_transform(
     motion_data: MotionData,
-    mut q_motion_players: Query<(
-        &MotionPlayer,
-        &TrajectoryPosePair,
-        &JointMap,
-        &mut Transform2d,
-    )>,
+    mut q_motion_players: Query<(&TrajectoryPoseStack, &JointMap, &mut Transform2d)>,
     mut q_transforms: Query<&mut Transform>,
 ) {
     let Some(root_joint) = motion_data.get().and_then(|asset| asset.get_joint(0)) else {
         return;
     };
 
-    for (motion_player, traj_pose_pair, joint_map, mut transform2d) in q_motion_players.iter_mut() {
+    for (traj_pose_stack, joint_map, mut transform2d) in q_motion_players.iter_mut() {
         let Some(mut root_joint_transform) = joint_map
             .get(root_joint.name())
             .and_then(|e| q_transforms.get_mut(*e).ok())
@@ -106,61 +122,56 @@ fn apply_root_transform(
             return;
         };
 
-        let mut final_root_config = [None; 2];
+        let mut final_root_config: Option<RootConfig> = None;
 
-        for i in 0..2 {
-            if let Some(traj_pose) = &traj_pose_pair[i] {
-                let root_transform2d = traj_pose.entity_root_transform2d;
-                let traj_inv_matrix = traj_pose.traj_root_matrix.inverse();
+        for slot in traj_pose_stack.slots.iter() {
+            let traj_pose = &slot.traj_pose;
+            let root_transform2d = traj_pose.entity_root_transform2d;
+            let traj_inv_matrix = traj_pose.traj_root_matrix.inverse();
 
-                let pose_matrix = traj_pose.pose.get_matrix(root_joint);
-                let (_, pose_rot, pose_pos) = pose_matrix.to_scale_rotation_translation();
+            // The root joint is the first joint.
+            let pose_matrix = traj_pose.pose.get_matrix(0);
+            let (_, pose_rot, pose_pos) = pose_matrix.to_scale_rotation_translation();
 
-                // Offset from trajectory root to current pose.
-                let offset_matrix = traj_inv_matrix * pose_matrix;
-                let (_, offset_rot, mut offset_pos) = offset_matrix.to_scale_rotation_translation();
-                offset_pos *= BVH_SCALE_RATIO;
+            // Offset from trajectory root to current pose.
+            let offset_matrix = traj_inv_matrix * pose_matrix;
+            let (_, offset_rot, mut offset_pos) = offset_matrix.to_scale_rotation_translation();
+            offset_pos *= BVH_SCALE_RATIO;
 
-                // Current pose forward direction.
-                let pose_forward = pose_matrix.transform_vector3(Vec3::Z).xz().normalize();
-                let pose_forward_angle = f32::atan2(pose_forward.x, pose_forward.y);
+            // Current pose forward direction.
+            let pose_forward = pose_matrix.transform_vector3(Vec3::Z).xz().normalize();
+            let pose_forward_angle = f32::atan2(pose_forward.x, pose_forward.y);
 
-                // Offset forward direction.
-                let offset_forward = offset_rot.mul_vec3(Vec3::Z).xz().normalize();
-                let offset_forward_angle = f32::atan2(offset_forward.x, offset_forward.y);
+            // Offset forward direction.
+            let offset_forward = offset_rot.mul_vec3(Vec3::Z).xz().normalize();
+            let offset_forward_angle = f32::atan2(offset_forward.x, offset_forward.y);
 
-                offset_pos = Quat::from_rotation_y(root_transform2d.angle).mul_vec3(offset_pos);
+            offset_pos = Quat::from_rotation_y(root_transform2d.angle).mul_vec3(offset_pos);
 
-                let translation = root_transform2d.translation + offset_pos.xz();
-                let angle = Quat::from_rotation_y(root_transform2d.angle + offset_forward_angle)
-                    .to_scaled_axis()
-                    .y;
+            let translation = root_transform2d.translation + offset_pos.xz();
+            let angle = Quat::from_rotation_y(root_transform2d.angle + offset_forward_angle)
+                .to_scaled_axis()
+                .y;
 
-                let local_y_pos = pose_pos.y;
-                let local_xz_rot =
-                    (Quat::from_rotation_y(pose_forward_angle).inverse() * pose_rot).normalize();
+            let local_y_pos = pose_pos.y;
+            let local_xz_rot =
+                (Quat::from_rotation_y(pose_forward_angle).inverse() * pose_rot).normalize();
 
-                final_root_config[i] = Some(RootConfig {
-                    world_transform2d: Transform2d { translation, angle },
-                    local_y_pos,
-                    local_xz_rot,
-                });
-            }
+            let root_config = RootConfig {
+                world_transform2d: Transform2d { translation, angle },
+                local_y_pos,
+                local_xz_rot,
+            };
+
+            // Fade from the blended result of the slots below.
+            final_root_config = Some(match final_root_config {
+                Some(blended) => RootConfig::lerp(blended, root_config, slot.fade),
+                None => root_config,
+            });
         }
 
-        let root_config = match (
-            motion_player.interp_factor,
-            final_root_config[0],
-            final_root_config[1],
-        ) {
-            // First data only.
-            (_, Some(root_config), None) | (0.0, Some(root_config), _) => root_config,
-            // Second data only.
-            (_, None, Some(root_config)) | (1.0, _, Some(root_config)) => root_config,
-            (t, Some(root_config0), Some(root_config1)) => {
-                RootConfig::lerp(root_config0, root_config1, t)
-            }
-            _ => continue,
+        let Some(root_config) = final_root_config else {
+            continue;
         };
 
         *transform2d = root_config.world_transform2d;
@@ -199,45 +210,70 @@ fn apply_root_transform(
     }
 }
 
+/// Where root motion continues from.
+fn root_motion_origin(
+    transform2d: &Transform2d,
+    simulation_bone: Option<&SimulationBone>,
+) -> Transform2d {
+    simulation_bone
+        .and_then(SimulationBone::animation_root)
+        .unwrap_or(*transform2d)
+}
+
 /// Handle the [`JumpToPose`] event.
 fn jump_to_pose(
     motion_data: MotionData,
     mut jump_evr: EventReader<JumpToPose>,
-    mut q_motion_players: Query<(&mut MotionPlayer, &mut TrajectoryPosePair, &Transform2d)>,
+    mut q_motion_players: Query<(
+        &mut TrajectoryPoseStack,
+        &Transform2d,
+        Option<&SimulationBone>,
+        Option<&mut Inertialization>,
+    )>,
+    motion_player_config: Res<MotionPlayerConfig>,
 ) {
-    let Some((pose_data, root_joint)) = motion_data
+    // The root joint is needed to anchor the trajectory.
+    let Some(motion_asset) = motion_data
         .get()
-        // SAFETY: We assume there is a root joint.
-        .map(|asset| (&asset.pose_data, asset.get_joint(0).unwrap()))
+        .filter(|asset| asset.get_joint(0).is_some())
     else {
         return;
     };
 
     for jump_to_pose in jump_evr.read() {
-        let Ok((mut motion_player, mut traj_pose_pair, transform2d)) =
+        let Ok((mut traj_pose_stack, transform2d, simulation_bone, inertialization)) =
             q_motion_players.get_mut(jump_to_pose.entity)
         else {
             continue;
         };
 
-        motion_player.switch_target_index();
-        let Some(pose) = jump_to_pose.get_pose(pose_data) else {
+        let Some(pose) = jump_to_pose.get_pose(&motion_asset.pose_data, motion_asset.joints())
+        else {
             continue;
         };
 
-        let index = motion_player.target_pair_index;
-        traj_pose_pair[index] = Some(TrajectoryPose {
+        let traj_pose = TrajectoryPose {
             motion_pose: **jump_to_pose,
-            traj_root_matrix: pose.get_matrix(root_joint),
-            entity_root_transform2d: *transform2d,
+            traj_root_matrix: pose.get_matrix(0),
+            entity_root_transform2d: root_motion_origin(transform2d, simulation_bone),
             pose,
             elapsed_time: 0.0,
-        });
+            playback_rate: 1.0,
+        };
+
+        match (motion_player_config.blend_mode, inertialization) {
+            (BlendMode::Inertialization, Some(mut inertialization)) => {
+                // Only the new animation is sampled, the transition is handled by the offsets.
+                traj_pose_stack.replace(traj_pose);
+                inertialization.switch();
+            }
+            _ => traj_pose_stack.push(traj_pose),
+        }
     }
 }
 
-fn update_interp_factor(
-    mut q_motion_players: Query<&mut MotionPlayer>,
+fn update_blend_weights(
+    mut q_traj_pose_stacks: Query<&mut TrajectoryPoseStack>,
     time: Res<Time>,
     motion_player_config: Res<MotionPlayerConfig>,
 ) {
@@ -246,39 +282,115 @@ fn update_interp_factor(
         "Interpolation duration cannot be 0 or below!"
     );
 
-    for mut motion_player in q_motion_players.iter_mut() {
-        motion_player
-            .update_interp_factor(time.delta_secs() / motion_player_config.interp_duration);
+    for mut traj_pose_stack in q_traj_pose_stacks.iter_mut() {
+        traj_pose_stack.update_fade(time.delta_secs() / motion_player_config.interp_duration);
+    }
+}
+
+/// Warp the playback rate of the target animation so that its root speed
+/// matches the speed of the predicted [`Trajectory`].
+fn update_playback_rate(
+    motion_data: MotionData,
+    mut q_traj_pose_stacks: Query<(&mut TrajectoryPoseStack, &Trajectory)>,
+    trajectory_config: Res<TrajectoryConfig>,
+    motion_player_config: Res<MotionPlayerConfig>,
+) {
+    let Some(motion_asset) = motion_data.get() else {
+        return;
+    };
+
+    let predict_time = trajectory_config.predict_time();
+
+    for (mut traj_pose_stack, trajectory) in q_traj_pose_stacks.iter_mut() {
+        let Some(traj_pose) = traj_pose_stack.target_mut() else {
+            continue;
+        };
+
+        // Average speed along the prediction trajectory.
+        let desired_speed = trajectory
+            .iter()
+            .skip(trajectory_config.history_count)
+            .map(|point| point.translation)
+            .collect::<Vec<_>>()
+            .windows(2)
+            .map(|points| Vec2::distance(points[0], points[1]))
+            .sum::<f32>()
+            / predict_time;
+
+        traj_pose.playback_rate = match traj_pose.root_speed(motion_asset, predict_time) {
+            Some(root_speed) if root_speed > LARGE_EPSILON => f32::clamp(
+                desired_speed / root_speed,
+                motion_player_config.min_playback_rate,
+                motion_player_config.max_playback_rate,
+            ),
+            // Warping an animation without root motion changes nothing but its tempo.
+            _ => 1.0,
+        };
     }
 }
 
 fn update_trajectory_pose_time(
-    mut q_traj_pose_pairs: Query<&mut TrajectoryPosePair>,
+    motion_data: MotionData,
+    mut q_traj_pose_stacks: Query<(&mut TrajectoryPoseStack, Entity)>,
+    mut notify_evw: EventWriter<AnimationNotify>,
     time: Res<Time>,
 ) {
-    for mut traj_pose_pair in q_traj_pose_pairs.iter_mut() {
-        for traj_pose in traj_pose_pair.iter_mut().filter_map(Some).flatten() {
-            traj_pose.update_time(time.delta_secs());
+    for (mut traj_pose_stack, entity) in q_traj_pose_stacks.iter_mut() {
+        for slot in traj_pose_stack.slots.iter_mut() {
+            let playback_rate = slot.traj_pose.playback_rate;
+            slot.traj_pose
+                .update_time(time.delta_secs() * playback_rate);
+        }
+
+        let (Some(motion_asset), Some(traj_pose)) = (motion_data.get(), traj_pose_stack.target())
+        else {
+            continue;
+        };
+
+        let delta_secs = time.delta_secs() * traj_pose.playback_rate;
+        let MotionPose {
+            chunk_index,
+            time: pose_time,
+        } = traj_pose.motion_pose;
+        let Some(duration) = motion_asset.pose_data.chunk_duration(chunk_index) else {
+            continue;
+        };
+
+        // Time past the duration is notified once it has been looped.
+        let start_time = f32::min(pose_time - delta_secs, duration);
+        let end_time = f32::min(pose_time, duration);
+        if start_time < end_time {
+            motion_asset.markers.notify(
+                entity,
+                chunk_index,
+                (Bound::Excluded(start_time), Bound::Included(end_time)),
+                &mut notify_evw,
+            );
         }
     }
 }
 
 fn loop_trajectory_pose_time(
     motion_data: MotionData,
-    mut q_traj_pose_pairs: Query<(&mut TrajectoryPosePair, &MotionPlayer, &Transform2d)>,
+    mut q_traj_pose_stacks: Query<(
+        &mut TrajectoryPoseStack,
+        &Transform2d,
+        Option<&SimulationBone>,
+        Entity,
+    )>,
+    mut notify_evw: EventWriter<AnimationNotify>,
 ) {
-    let Some(motion_asset) = motion_data.get() else {
-        return;
-    };
-
-    let Some(root_joint) = motion_asset.get_joint(0) else {
+    // The root joint is needed to anchor the trajectory.
+    let Some(motion_asset) = motion_data
+        .get()
+        .filter(|asset| asset.get_joint(0).is_some())
+    else {
         return;
     };
 
-    for (mut traj_pose_pair, motion_player, transform2d) in q_traj_pose_pairs.iter_mut() {
-        let index = motion_player.target_pair_index();
-
-        if let Some(traj_pose) = traj_pose_pair[index].as_mut() {
+    for (mut traj_pose_stack, transform2d, simulation_bone, entity) in q_traj_pose_stacks.iter_mut()
+    {
+        if let Some(traj_pose) = traj_pose_stack.target_mut() {
             let pose_data = &motion_asset.pose_data;
 
             if pose_data.is_chunk_loopable(traj_pose.motion_pose.chunk_index) != Some(true) {
@@ -296,11 +408,22 @@ fn loop_trajectory_pose_time(
 
             // Set time.
             traj_pose.motion_pose.time %= duration;
+            // Notify the markers from the start up to the looped time.
+            motion_asset.markers.notify(
+                entity,
+                traj_pose.motion_pose.chunk_index,
+                0.0..=traj_pose.motion_pose.time,
+                &mut notify_evw,
+            );
             // Loop time.
-            if let Some(pose) = traj_pose.motion_pose.get_pose(pose_data) {
+            if let Some(pose) = traj_pose
+                .motion_pose
+                .get_pose(pose_data, motion_asset.joints())
+            {
                 // Set transforms.
-                traj_pose.traj_root_matrix = pose.get_matrix(root_joint);
-                traj_pose.entity_root_transform2d = *transform2d;
+                traj_pose.traj_root_matrix = pose.get_matrix(0);
+                traj_pose.entity_root_transform2d =
+                    root_motion_origin(transform2d, simulation_bone);
                 traj_pose.pose = pose;
             }
         }
@@ -309,15 +432,16 @@ fn loop_trajectory_pose_time(
 
 fn apply_trajectory_pose(
     motion_data: MotionData,
-    mut q_traj_pose_pairs: Query<&mut TrajectoryPosePair>,
+    mut q_traj_pose_stacks: Query<&mut TrajectoryPoseStack>,
 ) {
-    let Some(pose_data) = motion_data.get().map(|asset| &asset.pose_data) else {
+    let Some(motion_asset) = motion_data.get() else {
         return;
     };
 
-    for mut traj_pose_pair in q_traj_pose_pairs.iter_mut() {
-        for traj_pose in traj_pose_pair.iter_mut().filter_map(Some).flatten() {
-            traj_pose.try_apply_pose(pose_data);
+    for mut traj_pose_stack in q_traj_pose_stacks.iter_mut() {
+        for slot in traj_pose_stack.slots.iter_mut() {
+            slot.traj_pose
+                .try_apply_pose(&motion_asset.pose_data, motion_asset.joints());
         }
     }
 }
@@ -326,24 +450,24 @@ fn apply_trajectory_pose(
 /// Note: This does not apply the root transform.
 fn pose_to_joint_transforms(
     motion_data: MotionData,
-    q_motion_players: Query<(&TrajectoryPosePair, &MotionPlayer, &JointMap)>,
+    q_motion_players: Query<(&TrajectoryPoseStack, &JointMap)>,
     mut q_transforms: Query<&mut Transform>,
 ) {
     let Some(motion_asset) = motion_data.get() else {
         return;
     };
 
-    for (traj_pose_pair, motion_player, joint_map) in q_motion_players.iter() {
-        let Some(pose) = traj_pose_pair.get_interpolated_pose(motion_player.interp_factor) else {
+    for (traj_pose_stack, joint_map) in q_motion_players.iter() {
+        let Some(pose) = traj_pose_stack.get_blended_pose() else {
             return;
         };
 
-        for joint in motion_asset.joints().iter().skip(1) {
+        for (joint_index, joint) in motion_asset.joints().iter().enumerate().skip(1) {
             if let Some(mut transform) = joint_map
                 .get(joint.name())
                 .and_then(|entity| q_transforms.get_mut(*entity).ok())
             {
-                let (pos, rot) = pose.get_pos_rot(joint);
+                let (pos, rot) = pose.get_pos_rot(joint_index);
                 transform.translation = joint.offset() + pos;
                 transform.rotation = rot;
             }
@@ -355,12 +479,18 @@ fn pose_to_joint_transforms(
 pub enum MotionPlayerSet {
     /// Handles [`JumpToPose`] event.
     JumpToPose,
-    /// Applies pose from [`TrajectoryPosePair`].
+    /// Applies pose from [`TrajectoryPoseStack`].
     ApplyPose,
-    /// Apply interpolated pose from [`TrajectoryPosePair`] to their respective joint transforms.
+    /// Apply blended pose from [`TrajectoryPoseStack`] to their respective joint transforms.
     ApplyJointTransform,
     /// Apply transform to root joint.
     ApplyRootTransform,
+    /// Apply [`AnimationLayers`] on top of the joint transforms.
+    ApplyLayers,
+    /// Apply [`Inertialization`] offsets on top of the joint transforms.
+    Inertialize,
+    /// Lock planted feet and solve [`FootIk`] on the legs.
+    FootIk,
     Interpolate,
 }
 
@@ -374,69 +504,84 @@ pub struct JumpToPose {
 #[derive(Bundle, Default)]
 pub struct MotionPlayerBundle {
     pub motion_player: MotionPlayer,
-    pub traj_pose_pair: TrajectoryPosePair,
-}
-
-#[derive(Component, Debug, Default, Deref, DerefMut)]
-pub struct TrajectoryPosePair([Option<TrajectoryPose>; 2]);
-
-impl TrajectoryPosePair {
-    pub fn get_interpolated_pose(&self, factor: f32) -> Option<Pose> {
-        let pose0 = self[0].as_ref().map(|traj_pose| &traj_pose.pose);
-        let pose1 = self[1].as_ref().map(|traj_pose| &traj_pose.pose);
-
-        match (factor, pose0, pose1) {
-            // Fully committed to the first one only.
-            (_, Some(pose), None) | (0.0, Some(pose), _) => Some(pose.clone()),
-            // Fully committed to the second one only.
-            (_, None, Some(pose)) | (1.0, _, Some(pose)) => Some(pose.clone()),
-            (t, Some(pose0), Some(pose1)) => Some(Pose::lerp(pose0, pose1, t)),
-            _ => None,
-        }
-    }
+    pub traj_pose_stack: TrajectoryPoseStack,
+    pub animation_layers: AnimationLayers,
+    pub inertialization: Inertialization,
+    pub foot_ik: FootIk,
 }
 
+/// Stack of animations being blended together (oldest first).
+///
+/// Each new animation fades in from the blended result of the animations below it,
+/// so rapid successive transitions never pop.
 #[derive(Component, Debug, Default)]
-pub struct MotionPlayer {
-    /// Interpolation factor between [`TrajectoryPosePair`].
-    interp_factor: f32,
-    /// The target index of [`TrajectoryPosePair`].
-    /// Also denotes which direction [`Self::interp_factor`] is going towards.
-    target_pair_index: usize,
+pub struct TrajectoryPoseStack {
+    slots: Vec<BlendSlot>,
 }
 
-impl MotionPlayer {
-    fn switch_target_index(&mut self) {
-        self.target_pair_index = (self.target_pair_index + 1) % 2;
+impl TrajectoryPoseStack {
+    /// Push a new animation to fade into.
+    pub fn push(&mut self, traj_pose: TrajectoryPose) {
+        let fade = match self.slots.is_empty() {
+            // Nothing to fade from.
+            true => 1.0,
+            false => 0.0,
+        };
+
+        self.slots.push(BlendSlot { traj_pose, fade });
     }
 
-    /// Update [`Self::interp_factor`] based on [`MotionPlayerConfig::interp_duration`].
-    fn update_interp_factor(&mut self, delta_factor: f32) {
-        match self.target_pair_index {
-            0 => {
-                self.interp_factor = f32::max(0.0, self.interp_factor - delta_factor);
-            }
-            1 => {
-                self.interp_factor = f32::min(1.0, self.interp_factor + delta_factor);
-            }
-            x => {
-                error!("Target frame index of MotionPlayer is neither 0 nor 1! It's {x}...");
-            }
+    /// Replace all animations with a fully faded in one.
+    pub fn replace(&mut self, traj_pose: TrajectoryPose) {
+        self.slots.clear();
+        self.push(traj_pose);
+    }
+
+    /// Fade in all slots by `delta_fade` and cull slots which no longer contribute.
+    fn update_fade(&mut self, delta_fade: f32) {
+        for slot in self.slots.iter_mut() {
+            slot.fade = f32::min(1.0, slot.fade + delta_fade);
+        }
+
+        // Everything below a fully faded in slot has a weight of zero.
+        if let Some(index) = self.slots.iter().rposition(|slot| slot.fade >= 1.0) {
+            self.slots.drain(..index);
         }
     }
-}
 
-// Getters
-impl MotionPlayer {
-    pub fn target_pair_index(&self) -> usize {
-        self.target_pair_index
+    /// Blend all poses, each fading in from the blended result of the ones below it.
+    pub fn get_blended_pose(&self) -> Option<JointPose> {
+        let (first, rest) = self.slots.split_first()?;
+
+        Some(
+            rest.iter()
+                .fold(first.traj_pose.pose.clone(), |pose, slot| {
+                    pose.blend(&slot.traj_pose.pose, slot.fade)
+                }),
+        )
+    }
+
+    /// The animation that is being faded into.
+    pub fn target(&self) -> Option<&TrajectoryPose> {
+        self.slots.last().map(|slot| &slot.traj_pose)
     }
 
-    pub fn interp_factor(&self) -> f32 {
-        self.interp_factor
+    fn target_mut(&mut self) -> Option<&mut TrajectoryPose> {
+        self.slots.last_mut().map(|slot| &mut slot.traj_pose)
     }
 }
 
+#[derive(Debug)]
+struct BlendSlot {
+    traj_pose: TrajectoryPose,
+    /// Fade in factor from the blended result of the slots below, from 0 to 1.
+    fade: f32,
+}
+
+/// Marks an entity that plays motion data through its [`TrajectoryPoseStack`].
+#[derive(Component, Debug, Default)]
+pub struct MotionPlayer;
+
 /// A pose frame inside [`PoseData`].
 #[derive(Debug, Clone, Copy)]
 pub struct MotionPose {
@@ -449,9 +594,9 @@ pub struct MotionPose {
 impl MotionPose {
     /// Get an interpolated pose from [`PoseData`].
     ///
-    /// Returns [`None`] when [`Self::chunk_index`] is invalid.
+    /// Returns [`None`] when [`Self::chunk_index`] is invalid or the chunk has less than 2 poses.
     #[must_use]
-    pub fn get_pose(&self, pose_data: &PoseData) -> Option<Pose> {
+    pub fn get_pose(&self, pose_data: &PoseData, joints: &[JointInfo]) -> Option<JointPose> {
         let interval_time = pose_data.interval_time();
 
         let poses = pose_data.get_chunk(self.chunk_index)?;
@@ -472,10 +617,13 @@ impl MotionPose {
         // Interpolation factor between start and end pose.
         let factor = leak / interval_time;
 
-        let start_pose = &poses[start];
-        let end_pose = &poses[end];
+        let start_pose = poses.get(start)?;
+        let end_pose = poses.get(end)?;
 
-        Some(Pose::lerp(start_pose, end_pose, factor))
+        Some(
+            JointPose::sample(start_pose, joints)
+                .blend(&JointPose::sample(end_pose, joints), factor),
+        )
     }
 }
 
@@ -486,16 +634,18 @@ pub struct TrajectoryPose {
     traj_root_matrix: Mat4,
     /// The matrix of the entity's root joint where the trajectory starts.
     entity_root_transform2d: Transform2d,
-    pose: Pose,
+    pose: JointPose,
     /// Time passed since [`Self::motion_pose`] was set.
     elapsed_time: f32,
+    /// Multiplier of the delta time this animation is played with.
+    playback_rate: f32,
 }
 
 impl TrajectoryPose {
     /// Apply pose from [`Self::motion_pose`] to [`Self::pose`] if possible. (See [`MotionPose`]).
     /// Returns true if successful and vice versa.
-    fn try_apply_pose(&mut self, pose_data: &PoseData) -> bool {
-        if let Some(pose) = self.motion_pose.get_pose(pose_data) {
+    fn try_apply_pose(&mut self, pose_data: &PoseData, joints: &[JointInfo]) -> bool {
+        if let Some(pose) = self.motion_pose.get_pose(pose_data, joints) {
             self.pose = pose;
             return true;
         }
@@ -504,10 +654,84 @@ impl TrajectoryPose {
     }
 
     /// Increase [`Self::elapsed_time`] and [`Self::motion_pose`] time.
+    ///
+    /// `delta_secs` is expected to be warped by [`Self::playback_rate`] already.
     pub fn update_time(&mut self, delta_secs: f32) {
         self.elapsed_time += delta_secs;
         self.motion_pose.time += delta_secs;
     }
+
+    /// Average root speed of the animation over the next `window` seconds.
+    ///
+    /// Sums the root distance travelled between every frame, so that curved paths are not
+    /// underestimated. Loopable chunks wrap around, others are measured until their end.
+    fn root_speed(&self, motion_asset: &MotionAsset, window: f32) -> Option<f32> {
+        let pose_data = &motion_asset.pose_data;
+        let root_joint = motion_asset.get_joint(0)?;
+        let chunk_index = self.motion_pose.chunk_index;
+        let interval_time = pose_data.interval_time();
+
+        let poses = pose_data.get_chunk(chunk_index)?;
+        let loopable = pose_data.is_chunk_loopable(chunk_index)?;
+        let duration = pose_data.chunk_duration(chunk_index)?;
+        if poses.len() < 2 {
+            return None;
+        }
+
+        let start_time = f32::clamp(self.motion_pose.time, 0.0, duration);
+        let end_time = match loopable {
+            true => start_time + window,
+            false => f32::min(start_time + window, duration),
+        };
+
+        if end_time - start_time < interval_time {
+            return None;
+        }
+
+        // Root translation inside a single loop, only reading the root channels.
+        let sample = |time: f32| {
+            let start = usize::min((time / interval_time) as usize, poses.len() - 2);
+            let factor = f32::clamp(
+                (time - start as f32 * interval_time) / interval_time,
+                0.0,
+                1.0,
+            );
+            Vec3::lerp(
+                poses[start].get_pos(root_joint),
+                poses[start + 1].get_pos(root_joint),
+                factor,
+            )
+            .xz()
+        };
+
+        // Root displacement of a single loop.
+        let loop_offset = sample(duration) - sample(0.0);
+        let root_translation = |time: f32| match loopable {
+            true => {
+                let loop_count = f32::floor(time / duration);
+                sample(time - loop_count * duration) + loop_offset * loop_count
+            }
+            false => sample(time),
+        };
+
+        let mut distance = 0.0;
+        let mut prev_translation = root_translation(start_time);
+        // Walk through every frame boundary inside the window.
+        let mut frame = (start_time / interval_time) as usize + 1;
+        loop {
+            let time = f32::min(frame as f32 * interval_time, end_time);
+            let translation = root_translation(time);
+            distance += Vec2::distance(prev_translation, translation);
+            prev_translation = translation;
+
+            if time >= end_time {
+                break;
+            }
+            frame += 1;
+        }
+
+        Some(distance * BVH_SCALE_RATIO / (end_time - start_time))
+    }
 }
 
 // Getters
@@ -519,12 +743,34 @@ impl TrajectoryPose {
     pub fn elapsed_time(&self) -> f32 {
         self.elapsed_time
     }
+
+    pub fn playback_rate(&self) -> f32 {
+        self.playback_rate
+    }
 }
 
 #[derive(Resource, Debug)]
 pub struct MotionPlayerConfig {
-    /// Duration for [`MotionPlayer::interp_factor`] to go between 0 and 1.
+    /// Duration for a [`TrajectoryPoseStack`] slot to fade in.
     interp_duration: f32,
+    /// How to transition between animations.
+    pub blend_mode: BlendMode,
+    /// Lower bound of the warped playback rate.
+    pub min_playback_rate: f32,
+    /// Upper bound of the warped playback rate.
+    ///
+    /// Set both bounds to 1.0 to disable time warping.
+    pub max_playback_rate: f32,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Sample both animations and interpolate between them.
+    #[default]
+    CrossFade,
+    /// Sample only the new animation and decay the offset from the previous output
+    /// with a critically damped spring.
+    Inertialization,
 }
 
 impl MotionPlayerConfig {
@@ -18,25 +18,7 @@ impl Pose {
     /// Get position and rotation.
     #[must_use]
     pub fn get_pos_rot(&self, joint_info: &JointInfo) -> (Vec3, Quat) {
-        let mut pos = Vec3::ZERO;
-        let mut euler = Vec3::ZERO;
-
-        for pose_ref in joint_info.pose_refs() {
-            let i = pose_ref.motion_index();
-            match pose_ref.channel_type() {
-                ChannelType::RotationX => euler.x = self[i].to_radians(),
-                ChannelType::RotationY => euler.y = self[i].to_radians(),
-                ChannelType::RotationZ => euler.z = self[i].to_radians(),
-                ChannelType::PositionX => pos.x = self[i],
-                ChannelType::PositionY => pos.y = self[i],
-                ChannelType::PositionZ => pos.z = self[i],
-            }
-        }
-
-        (
-            pos,
-            Quat::from_euler(EulerRot::XYZ, euler.x, euler.y, euler.z),
-        )
+        self.get_joint_pos_rot(joint_info)
     }
 
     /// Get position and rotation in euler angles (in radians).
@@ -121,15 +103,77 @@ impl Pose {
         Mat4::from_rotation_translation(rot, pos)
     }
 
-    pub fn lerp(&self, rhs: &Self, factor: f32) -> Self {
-        let data = self
-            .0
-            .iter()
-            .enumerate()
-            .map(|(i, x)| f32::lerp(*x, rhs[i], factor))
-            .collect::<Vec<_>>();
+    /// Get position and rotation of any [`JointTrait`].
+    #[must_use]
+    pub fn get_joint_pos_rot(&self, joint: &impl JointTrait) -> (Vec3, Quat) {
+        let mut pos = Vec3::ZERO;
+        let mut euler = Vec3::ZERO;
 
-        Self(data)
+        for channel in joint.channels() {
+            let i = channel.motion_index();
+            match channel.channel_type() {
+                ChannelType::RotationX => euler.x = self[i].to_radians(),
+                ChannelType::RotationY => euler.y = self[i].to_radians(),
+                ChannelType::RotationZ => euler.z = self[i].to_radians(),
+                ChannelType::PositionX => pos.x = self[i],
+                ChannelType::PositionY => pos.y = self[i],
+                ChannelType::PositionZ => pos.z = self[i],
+            }
+        }
+
+        (
+            pos,
+            Quat::from_euler(EulerRot::XYZ, euler.x, euler.y, euler.z),
+        )
+    }
+}
+
+/// Position and rotation of each joint, sampled once from a [`Pose`].
+///
+/// Indexed like the joints it was sampled with. Positions are the position channels
+/// (zero for joints without any), like [`Pose::get_pos`].
+#[derive(Default, Debug, Deref, DerefMut, Clone)]
+pub struct JointPose(pub Vec<(Vec3, Quat)>);
+
+impl JointPose {
+    #[must_use]
+    pub fn sample<J: JointTrait>(pose: &Pose, joints: &[J]) -> Self {
+        Self(
+            joints
+                .iter()
+                .map(|joint| pose.get_joint_pos_rot(joint))
+                .collect(),
+        )
+    }
+
+    /// Get position and rotation of the joint at `joint_index`.
+    #[must_use]
+    pub fn get_pos_rot(&self, joint_index: usize) -> (Vec3, Quat) {
+        self[joint_index]
+    }
+
+    /// Get the local matrix of the joint at `joint_index`.
+    #[must_use]
+    pub fn get_matrix(&self, joint_index: usize) -> Mat4 {
+        let (pos, rot) = self.get_pos_rot(joint_index);
+        Mat4::from_rotation_translation(rot, pos)
+    }
+
+    /// Blend towards `rhs` per joint, interpolating positions linearly and
+    /// rotations spherically so that they always take the shortest path.
+    #[must_use]
+    pub fn blend(&self, rhs: &Self, factor: f32) -> Self {
+        Self(
+            self.iter()
+                .zip(rhs.iter())
+                .map(|(&(pos0, rot0), &(pos1, rot1))| {
+                    (
+                        Vec3::lerp(pos0, pos1, factor),
+                        Quat::slerp(rot0, rot1, factor),
+                    )
+                })
+                .collect(),
+        )
     }
 }
 
@@ -173,6 +217,14 @@ impl PoseData {
         self.loopables.push(bvh.loopable());
     }
 
+    /// Append raw poses as a chunk.
+    #[cfg(test)]
+    pub(crate) fn append_poses(&mut self, poses: Vec<Pose>, loopable: bool) {
+        self.offsets.push_chunk(poses.len());
+        self.poses.extend(poses);
+        self.loopables.push(loopable);
+    }
+
     pub fn is_chunk_loopable(&self, chunk_index: usize) -> Option<bool> {
         self.loopables.get(chunk_index).copied()
     }
@@ -181,6 +233,13 @@ impl PoseData {
         self.loopables[chunk_index]
     }
 
+    /// Duration of a chunk in seconds.
+    pub fn chunk_duration(&self, chunk_index: usize) -> Option<f32> {
+        let poses = self.get_chunk(chunk_index)?;
+        // 2 poses is an animation segment, so we need to deduct by 1.
+        Some(self.interval_time * poses.len().saturating_sub(1) as f32)
+    }
+
     /// Calculate the time value from a chunk offset index.
     pub fn time_from_chunk_offset(&self, chunk_offset: usize) -> f32 {
         chunk_offset as f32 * self.interval_time
@@ -210,3 +269,54 @@ impl ChunkIterator for PoseData {
         &self.poses
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, FRAC_PI_8};
+
+    use super::*;
+
+    #[test]
+    fn joint_pose_blend_interpolates_each_joint() {
+        let lhs = JointPose(vec![
+            (Vec3::ZERO, Quat::IDENTITY),
+            (Vec3::X, Quat::from_rotation_y(FRAC_PI_2)),
+        ]);
+        let rhs = JointPose(vec![
+            (Vec3::new(2.0, 4.0, 0.0), Quat::from_rotation_x(FRAC_PI_2)),
+            (Vec3::X, Quat::from_rotation_y(-FRAC_PI_4)),
+        ]);
+
+        let start = lhs.blend(&rhs, 0.0);
+        let end = lhs.blend(&rhs, 1.0);
+        for i in 0..2 {
+            assert!(start[i].0.distance(lhs[i].0) < 1e-6);
+            assert!(start[i].1.angle_between(lhs[i].1) < 1e-3);
+            assert!(end[i].0.distance(rhs[i].0) < 1e-6);
+            assert!(end[i].1.angle_between(rhs[i].1) < 1e-3);
+        }
+
+        let halfway = lhs.blend(&rhs, 0.5);
+        assert!(halfway[0].0.distance(Vec3::new(1.0, 2.0, 0.0)) < 1e-6);
+        assert!(
+            halfway[0]
+                .1
+                .angle_between(Quat::from_rotation_x(FRAC_PI_2 * 0.5))
+                < 1e-3
+        );
+        assert!(halfway[1].1.angle_between(Quat::from_rotation_y(FRAC_PI_8)) < 1e-3);
+    }
+
+    #[test]
+    fn joint_pose_blend_takes_the_shortest_path() {
+        let rot = Quat::from_rotation_z(0.4);
+        // Same rotation on the other side of the hypersphere.
+        let lhs = JointPose(vec![(Vec3::ZERO, rot)]);
+        let rhs = JointPose(vec![(Vec3::ZERO, -rot)]);
+
+        for factor in [0.25, 0.5, 0.75] {
+            let blended = lhs.blend(&rhs, factor);
+            assert!(blended[0].1.angle_between(rot) < 1e-3);
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! Reconcile the simulated character controller with the root motion of the animation.
+
+use std::f32::consts::PI;
+
+use bevy::math::Affine2;
+use bevy::prelude::*;
+
+use crate::bvh_manager::bvh_player::JointMap;
+use crate::player::MovementConfig;
+use crate::trajectory::{Trajectory, TrajectoryConfig};
+use crate::transform2d::Transform2d;
+use crate::{GameMode, MotionUpdate, BVH_SCALE_RATIO, LARGE_EPSILON};
+
+use super::motion_player::{MotionPlayer, MotionPlayerSet, TrajectoryPoseStack};
+use super::MotionData;
+
+pub(super) struct SimulationBonePlugin;
+
+impl Plugin for SimulationBonePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SimulationBoneConfig {
+            mode: RootMotionMode::Animation,
+            max_distance: 0.2,
+            max_angle: f32::to_radians(45.0),
+            animation_pull: 5.0,
+            simulation_pull: 0.0,
+        })
+        .add_systems(PreUpdate, init_simulation_bone)
+        .add_systems(
+            MotionUpdate,
+            reconcile_root
+                .after(MotionPlayerSet::ApplyRootTransform)
+                .before(MotionPlayerSet::Inertialize)
+                .run_if(in_state(GameMode::Play)),
+        );
+    }
+}
+
+fn init_simulation_bone(mut commands: Commands, q_players: Query<Entity, Added<MotionPlayer>>) {
+    for entity in q_players.iter() {
+        commands.entity(entity).insert(SimulationBone::default());
+    }
+}
+
+/// Move the simulation along the predicted [`Trajectory`] and pull it together
+/// with the animation root (written into [`Transform2d`] by the motion player).
+///
+/// The entity is placed at the simulation while the root joint
+/// is offset to where the animation root should be displayed.
+fn reconcile_root(
+    motion_data: MotionData,
+    mut q_simulation_bones: Query<(
+        &mut SimulationBone,
+        &mut Transform2d,
+        &Trajectory,
+        &TrajectoryPoseStack,
+        &JointMap,
+    )>,
+    mut q_transforms: Query<&mut Transform>,
+    config: Res<SimulationBoneConfig>,
+    trajectory_config: Res<TrajectoryConfig>,
+    movement_config: Res<MovementConfig>,
+    time: Res<Time>,
+) {
+    let Some(root_joint) = motion_data.get().and_then(|asset| asset.get_joint(0)) else {
+        return;
+    };
+
+    let dt = time.delta_secs();
+
+    for (mut simulation_bone, mut transform2d, trajectory, traj_pose_stack, joint_map) in
+        q_simulation_bones.iter_mut()
+    {
+        let Some(mut root_joint_transform) = joint_map
+            .get(root_joint.name())
+            .and_then(|e| q_transforms.get_mut(*e).ok())
+        else {
+            continue;
+        };
+
+        // Without a sampled pose, the root motion did not write an animation root
+        // and [`Transform2d`] still holds the simulation.
+        if config.mode == RootMotionMode::Animation || traj_pose_stack.target().is_none() {
+            // Remove the display offset and start over from the next animation root.
+            if simulation_bone.animation_root.take().is_some() {
+                root_joint_transform.translation.x = 0.0;
+                root_joint_transform.translation.z = 0.0;
+            }
+            continue;
+        }
+
+        let animation_root = *transform2d;
+        if simulation_bone
+            .animation_root
+            .replace(animation_root)
+            .is_none()
+        {
+            // Start off where the animation is.
+            simulation_bone.simulation = animation_root;
+            simulation_bone.correction = Affine2::IDENTITY;
+        }
+
+        // Animation root with the corrections so far.
+        let mut display =
+            Transform2d::from_affine2(simulation_bone.correction * animation_root.to_affine2());
+        let simulation = &mut simulation_bone.simulation;
+
+        // Follow the first segment of the prediction.
+        let current_index = trajectory_config.history_count;
+        if let (Some(current), Some(next)) = (
+            trajectory.get(current_index),
+            trajectory.get(current_index + 1),
+        ) {
+            let velocity =
+                (next.translation - current.translation) / trajectory_config.interval_time;
+            simulation.translation += velocity * dt;
+
+            if velocity.length_squared() > LARGE_EPSILON {
+                let target_angle = f32::atan2(velocity.x, velocity.y);
+                simulation.angle = lerp_angle(
+                    simulation.angle,
+                    target_angle,
+                    f32::min(1.0, movement_config.lerp_factor * dt),
+                );
+            }
+        }
+
+        // Pull towards each other.
+        let simulation_factor = pull_factor(config.simulation_pull, dt);
+        simulation.translation = simulation
+            .translation
+            .lerp(display.translation, simulation_factor);
+        simulation.angle = lerp_angle(simulation.angle, display.angle, simulation_factor);
+
+        let animation_factor = pull_factor(config.animation_pull, dt);
+        display.translation = display
+            .translation
+            .lerp(simulation.translation, animation_factor);
+        display.angle = lerp_angle(display.angle, simulation.angle, animation_factor);
+
+        // Clamp the animation around the simulation.
+        let offset =
+            (display.translation - simulation.translation).clamp_length_max(config.max_distance);
+        display.translation = simulation.translation + offset;
+        let angle_offset =
+            wrap_angle(display.angle - simulation.angle).clamp(-config.max_angle, config.max_angle);
+        display.angle = simulation.angle + angle_offset;
+
+        let simulation = *simulation;
+        simulation_bone.correction = display.to_affine2() * animation_root.to_affine2().inverse();
+
+        *transform2d = simulation;
+
+        // Offset the root joint (in bvh units) in the local space of the simulation.
+        let local_offset =
+            simulation.to_affine2().matrix2.inverse().mul_vec2(offset) / BVH_SCALE_RATIO;
+        root_joint_transform.translation.x = local_offset.x;
+        root_joint_transform.translation.z = local_offset.y;
+        root_joint_transform.rotation =
+            Quat::from_rotation_y(angle_offset) * root_joint_transform.rotation;
+    }
+}
+
+/// Factor to move by within `dt` given a `rate` per second.
+fn pull_factor(rate: f32, dt: f32) -> f32 {
+    1.0 - f32::exp(-f32::max(rate, 0.0) * dt)
+}
+
+/// Wrap an angle into [-PI, PI].
+fn wrap_angle(angle: f32) -> f32 {
+    (angle + PI).rem_euclid(2.0 * PI) - PI
+}
+
+/// Interpolate between 2 angles through the shortest path.
+fn lerp_angle(from: f32, to: f32, factor: f32) -> f32 {
+    from + wrap_angle(to - from) * factor
+}
+
+/// Simulated position of a [`MotionPlayer`] driven by the character controller.
+#[derive(Component, Debug)]
+pub struct SimulationBone {
+    simulation: Transform2d,
+    /// Uncorrected animation root of the last frame.
+    ///
+    /// [`None`] when [`RootMotionMode::Animation`] is in use.
+    animation_root: Option<Transform2d>,
+    /// Transforms the uncorrected animation root to where it is displayed.
+    correction: Affine2,
+}
+
+impl Default for SimulationBone {
+    fn default() -> Self {
+        Self {
+            simulation: Transform2d::default(),
+            animation_root: None,
+            correction: Affine2::IDENTITY,
+        }
+    }
+}
+
+// Getters
+impl SimulationBone {
+    pub fn simulation(&self) -> Transform2d {
+        self.simulation
+    }
+
+    /// Uncorrected animation root, root motion should continue from this
+    /// instead of the entity's [`Transform2d`].
+    pub fn animation_root(&self) -> Option<Transform2d> {
+        self.animation_root
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct SimulationBoneConfig {
+    pub mode: RootMotionMode,
+    /// Maximum distance between the simulation and the displayed animation root.
+    pub max_distance: f32,
+    /// Maximum angle (in radians) between the simulation and the displayed animation root.
+    pub max_angle: f32,
+    /// Rate per second at which the animation is pulled towards the simulation.
+    pub animation_pull: f32,
+    /// Rate per second at which the simulation is pulled towards the animation.
+    pub simulation_pull: f32,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RootMotionMode {
+    /// The animation root motion owns the entity position.
+    #[default]
+    Animation,
+    /// A simulated character controller owns the entity position.
+    Simulation,
+}
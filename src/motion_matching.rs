@@ -1,22 +1,36 @@
 use bevy::prelude::*;
-use std::time::Instant;
+use bevy::utils::HashMap;
 
+use action_clip::{ActionClipPlugin, ActionClipState};
+use brute_force_match::BruteForceMatchPlugin;
 use kdtree_match::KdTreeMatchPlugin;
 use kmeans_match::KMeansMatchPlugin;
+use match_history::{MatchHistory, MatchHistoryPlugin};
+use search_index::MOTION_DATA_PATH;
+use search_schedule::{SearchSchedule, SearchSchedulePlugin};
 
+pub mod action_clip;
+pub mod brute_force_match;
 pub mod kdtree_match;
 pub mod kmeans_match;
+pub mod match_history;
+pub mod search_index;
+pub mod search_schedule;
 
 use crate::bvh_manager::bvh_player::JointMap;
 use crate::motion::chunk::ChunkIterator;
 use crate::motion::motion_asset::MotionAsset;
 use crate::motion::motion_player::{
-    JumpToPose, MotionPlayer, MotionPlayerConfig, MotionPose, TrajectoryPosePair,
+    JumpToPose, MotionPlayerConfig, MotionPose, TrajectoryPoseStack,
 };
+use crate::motion::pose_data::{JointPose, PoseData};
+use crate::motion::trajectory_data::TrajectoryDataPoint;
 use crate::motion::{MotionData, MotionHandle};
-use crate::trajectory::{Trajectory, TrajectoryConfig, TrajectoryDistance, TrajectoryPoint};
+use crate::trajectory::{
+    MovementDirection, Trajectory, TrajectoryConfig, TrajectoryDistance, TrajectoryPoint,
+};
 use crate::ui::play_mode::MotionMatchingResult;
-use crate::{GameMode, MainSet, Method, BVH_SCALE_RATIO};
+use crate::{GameMode, MainSet, MotionUpdate, BVH_SCALE_RATIO};
 
 use peak_alloc::PeakAlloc;
 #[global_allocator]
@@ -27,8 +41,9 @@ pub struct MotionMatchingPlugin;
 impl Plugin for MotionMatchingPlugin {
     fn build(&self, app: &mut App) {
         app.configure_sets(
-            Update,
+            MotionUpdate,
             (
+                MotionMatchingSet::Action,
                 MotionMatchingSet::Flow,
                 MotionMatchingSet::PredictionMatch,
                 MotionMatchingSet::GlobalMatch,
@@ -39,25 +54,33 @@ impl Plugin for MotionMatchingPlugin {
                 .run_if(in_state(GameMode::Play)),
         );
 
-        app.add_plugins(KdTreeMatchPlugin)
+        app.add_plugins(BruteForceMatchPlugin)
+            .add_plugins(KdTreeMatchPlugin)
             .add_plugins(KMeansMatchPlugin)
+            .add_plugins(MatchHistoryPlugin)
+            .add_plugins(SearchSchedulePlugin)
+            .add_plugins(ActionClipPlugin)
             .insert_resource(MatchConfig {
                 max_match_count: 5,
                 match_threshold: 0.3,
                 pred_match_threshold: 0.15,
+                continuation_bonus: 0.05,
+                continuation_window: 0.2,
+                min_switch_improvement: 0.02,
+                transition_costs: HashMap::default(),
+                cooldown_duration: 2.0,
+                cooldown_window: 0.25,
+                cooldown_penalty: 0.1,
             })
             .add_event::<TrajectoryMatch>()
             .add_event::<PredictionMatch>()
             .add_event::<NearestTrajectories>()
             .add_systems(PreStartup, load_motion_data)
             .add_systems(
-                Update,
+                MotionUpdate,
                 (
                     flow.in_set(MotionMatchingSet::Flow),
                     prediction_match.in_set(MotionMatchingSet::PredictionMatch),
-                    trajectory_match
-                        .in_set(MotionMatchingSet::GlobalMatch)
-                        .run_if(in_state(Method::BruteForceKNN)),
                     pose_match.in_set(MotionMatchingSet::PoseMatch),
                 ),
             );
@@ -65,36 +88,60 @@ impl Plugin for MotionMatchingPlugin {
 }
 
 pub fn load_motion_data(mut commands: Commands, asset_server: Res<AssetServer>) {
-    let file_path = "motion_data/motion_data.json";
+    let file_path = format!("{MOTION_DATA_PATH}.json");
     let motion_data = asset_server.load::<MotionAsset>(file_path);
 
     commands.insert_resource(MotionHandle(motion_data));
 }
 
 fn flow(
-    q_players: Query<(&MotionPlayer, &TrajectoryPosePair, Entity)>,
+    mut q_players: Query<(
+        &TrajectoryPoseStack,
+        Option<&mut SearchSchedule>,
+        Option<&MovementDirection>,
+        Option<&ActionClipState>,
+        Entity,
+    )>,
     trajectory_config: Res<TrajectoryConfig>,
     motion_player_config: Res<MotionPlayerConfig>,
+    time: Res<Time>,
     mut traj_match_evw: EventWriter<TrajectoryMatch>,
     mut pred_match_evw: EventWriter<PredictionMatch>,
 ) {
-    let predict_time = trajectory_config.predict_time();
-    let interp_duration = motion_player_config.interp_duration();
-
-    let max_elapsed_time = predict_time - interp_duration;
+    let max_elapsed_time = prediction_search_interval(&trajectory_config, &motion_player_config);
     assert!(
         max_elapsed_time > 0.0,
         "Prediction duration cannot be shorter than interpolation duration!"
     );
 
-    for (motion_player, traj_pose_pair, entity) in q_players.iter() {
-        let index = motion_player.target_pair_index();
-        let Some(traj_pose) = &traj_pose_pair[index] else {
+    for (traj_pose_stack, search_schedule, movement_direction, action_clip_state, entity) in
+        q_players.iter_mut()
+    {
+        // Motion matching is suspended while an action clip plays.
+        if action_clip_state.is_some_and(ActionClipState::is_playing) {
+            continue;
+        }
+
+        let direction = movement_direction.map(|d| **d).unwrap_or_default();
+        let Some(traj_pose) = traj_pose_stack.target() else {
             // Find a new animation to play.
             traj_match_evw.send(TrajectoryMatch(entity));
+            if let Some(mut search_schedule) = search_schedule {
+                search_schedule.reset(direction);
+            }
             continue;
         };
 
+        if let Some(mut search_schedule) = search_schedule {
+            let search_due = search_schedule.tick(time.delta());
+
+            if search_due || search_schedule.should_force_search(direction) {
+                traj_match_evw.send(TrajectoryMatch(entity));
+                search_schedule.reset(direction);
+                continue;
+            }
+        }
+
         match traj_pose.elapsed_time() < max_elapsed_time {
             true => {
                 // Continue playing the animation...
@@ -115,9 +162,10 @@ fn flow(
 /// Performs a match [`PredictionMatch`] event.
 fn prediction_match(
     motion_data: MotionData,
-    q_trajectory: Query<(&Trajectory, &Transform)>,
+    q_trajectory: Query<(&Trajectory, &Transform, Option<&ActionClipState>)>,
     match_config: Res<MatchConfig>,
     trajectory_config: Res<TrajectoryConfig>,
+    motion_player_config: Res<MotionPlayerConfig>,
     mut pred_match_evr: EventReader<PredictionMatch>,
     mut traj_match_evw: EventWriter<TrajectoryMatch>,
 ) {
@@ -129,12 +177,18 @@ fn prediction_match(
     let pose_data = &motion_asset.pose_data;
 
     let num_points = trajectory_config.num_predict_points();
+    let interp_duration = motion_player_config.interp_duration();
 
     for pred_match in pred_match_evr.read() {
-        let Ok((trajectory, transform)) = q_trajectory.get(pred_match.entity) else {
+        let Ok((trajectory, transform, action_clip_state)) = q_trajectory.get(pred_match.entity)
+        else {
             continue;
         };
 
+        if action_clip_state.is_some_and(ActionClipState::is_playing) {
+            continue;
+        }
+
         let inv_matrix = transform.compute_matrix().inverse();
         let traj = trajectory
             .iter()
@@ -148,7 +202,7 @@ fn prediction_match(
             })
             .collect::<Vec<_>>();
 
-        let mut chunk_offset = trajectory_data.chunk_offset_from_time(pred_match.time);
+        let chunk_offset = trajectory_data.chunk_offset_from_time(pred_match.time);
 
         let (Some(data_traj_chunk), Some(loopable)) = (
             trajectory_data.get_chunk(pred_match.chunk_index),
@@ -158,160 +212,119 @@ fn prediction_match(
             continue;
         };
 
+        let num_remaining = data_traj_chunk.len().saturating_sub(chunk_offset);
+        let remaining_time =
+            trajectory_data.time_from_chunk_offset(num_remaining.saturating_sub(1));
+
         // Do we have enough trajectories?
-        if data_traj_chunk.len().saturating_sub(chunk_offset) < num_points {
-            match loopable {
-                true => chunk_offset = 0,
-                false => {
+        let data_matrices = if num_remaining >= num_points {
+            data_traj_chunk[chunk_offset..chunk_offset + num_points]
+                .iter()
+                .map(|point| point.matrix)
+                .collect::<Vec<_>>()
+        } else if loopable {
+            match loop_trajectory_matrices(
+                motion_asset,
+                pred_match.chunk_index,
+                chunk_offset,
+                num_points,
+            ) {
+                Some(data_matrices) => data_matrices,
+                None => {
                     traj_match_evw.send(TrajectoryMatch(pred_match.entity));
                     continue;
                 }
             }
-        }
-
-        let data_traj = &data_traj_chunk[chunk_offset..chunk_offset + num_points];
+        } else if num_remaining > 1 && remaining_time >= interp_duration {
+            // Keep playing as long as there is still time to blend into the next animation,
+            // only the remaining part of the prediction trajectory is matched.
+            data_traj_chunk[chunk_offset..]
+                .iter()
+                .map(|point| point.matrix)
+                .collect::<Vec<_>>()
+        } else {
+            traj_match_evw.send(TrajectoryMatch(pred_match.entity));
+            continue;
+        };
 
         // Center point of trajectory.
-        let data_inv_matrix = data_traj[0].matrix.inverse();
-        let data_traj = data_traj
+        let data_inv_matrix = data_matrices[0].inverse();
+        let data_traj = data_matrices
             .iter()
-            .map(|point| {
-                let (.., translation) = point.matrix.to_scale_rotation_translation();
+            .map(|matrix| {
+                let (.., translation) = matrix.to_scale_rotation_translation();
                 TrajectoryPoint {
                     translation: data_inv_matrix.transform_point3(translation).xz()
                         * BVH_SCALE_RATIO,
-                    velocity: point.velocity * BVH_SCALE_RATIO,
+                    // Velocity is not used for matching.
+                    velocity: Vec2::ZERO,
                 }
             })
             .collect::<Vec<_>>();
 
-        if traj.distance(&data_traj) > match_config.pred_match_threshold {
+        if traj[..data_traj.len()].distance(&data_traj) > match_config.pred_match_threshold {
             traj_match_evw.send(TrajectoryMatch(pred_match.entity));
         }
     }
 }
 
-/// Search for the best match trajectory from [`MotionData`].
+/// Trajectory matrices of a loopable chunk starting from `chunk_offset`,
+/// wrapping around the loop seam of the animation.
 ///
-/// Performs a match every [`TrajectoryMatch`] event.
-fn trajectory_match(
-    motion_data: MotionData,
-    q_trajectory: Query<(&Trajectory, &Transform)>,
-    trajectory_config: Res<TrajectoryConfig>,
-    match_config: Res<MatchConfig>,
-    mut motion_matching_result: ResMut<MotionMatchingResult>,
-    mut match_evr: EventReader<TrajectoryMatch>,
-    mut nearest_trajectories_evw: EventWriter<NearestTrajectories>,
-) {
-    // println!("Brute Force KNN Method");
-    PEAK_ALLOC.reset_peak_usage();
-    let Some(motion_data) = motion_data.get() else {
-        return;
-    };
-
-    let num_segments = trajectory_config.num_segments();
-    let num_points = trajectory_config.num_points();
-
-    for traj_match in match_evr.read() {
-        let entity = **traj_match;
-        let Ok((traj, transform)) = q_trajectory.get(entity) else {
-            continue;
-        };
-
-        let inv_matrix = transform.compute_matrix().inverse();
-        let traj = traj
-            .iter()
-            .map(|&(mut point)| {
-                point.translation = inv_matrix
-                    .transform_point3(Vec3::new(point.translation.x, 0.0, point.translation.y))
-                    .xz();
-                point
-            })
-            .collect::<Vec<_>>();
+/// Points beyond the end of the animation are sampled from its start and shifted by the
+/// root displacement of every completed loop, the same way the [`MotionAsset`] is built.
+fn loop_trajectory_matrices(
+    motion_asset: &MotionAsset,
+    chunk_index: usize,
+    chunk_offset: usize,
+    num_points: usize,
+) -> Option<Vec<Mat4>> {
+    let pose_data = &motion_asset.pose_data;
+    let root_joint = motion_asset.get_joint(0)?;
 
-        let mut nearest_trajs = Vec::with_capacity(match_config.max_match_count);
+    let poses = pose_data.get_chunk(chunk_index)?;
+    // 2 poses is an animation segment, so we need to deduct by 1.
+    let duration = pose_data.interval_time() * poses.len().saturating_sub(1) as f32;
+    if duration <= 0.0 {
+        return None;
+    }
 
-        let start_time = Instant::now();
-        for (chunk_index, chunk) in motion_data.trajectory_data.iter_chunk().enumerate() {
-            // Number of trajectory in this chunk.
-            let num_trajectories = chunk.len() - num_segments;
-
-            for chunk_offset in 0..num_trajectories {
-                let data_traj = &chunk[chunk_offset..chunk_offset + num_points];
-
-                // Center point of trajectory
-                let data_inv_matrix = data_traj[trajectory_config.history_count].matrix.inverse();
-
-                let data_traj = data_traj
-                    .iter()
-                    .map(|point| {
-                        let (.., translation) = point.matrix.to_scale_rotation_translation();
-                        TrajectoryPoint {
-                            translation: data_inv_matrix.transform_point3(translation).xz()
-                                * BVH_SCALE_RATIO,
-                            velocity: point.velocity * BVH_SCALE_RATIO,
-                        }
-                    })
-                    .collect::<Vec<_>>();
-
-                let distance = traj.distance(&data_traj);
-
-                // Distance must be below the threshold.
-                if distance > match_config.match_threshold {
-                    continue;
-                }
+    // Root displacement of a single loop.
+    let loop_offset = poses.last()?.get_pos(root_joint) - poses.first()?.get_pos(root_joint);
 
-                if nearest_trajs.len() < match_config.max_match_count {
-                    // Stack not yet full, push into it
-                    nearest_trajs.push(MatchTrajectory {
-                        distance,
-                        chunk_index,
-                        chunk_offset,
-                    });
-                } else if let Some(worst_match) = nearest_trajs.last_mut() {
-                    if distance < worst_match.distance {
-                        *worst_match = MatchTrajectory {
-                            distance,
-                            chunk_index,
-                            chunk_offset,
-                        };
-                    }
-                }
+    (chunk_offset..chunk_offset + num_points)
+        .map(|offset| {
+            let time = motion_asset.trajectory_data.time_from_chunk_offset(offset);
+            let loop_count = f32::floor(time / duration);
 
-                // Sort so that trajectories with the largest distance
-                // is placed as the final element in the stack
-                nearest_trajs.sort_by(|t0, t1| t0.distance.total_cmp(&t1.distance));
+            let pose = MotionPose {
+                chunk_index,
+                time: time - loop_count * duration,
             }
-        }
-
-        let knn_search_peak_memory = PEAK_ALLOC.peak_usage_as_mb();
-        let traj_duration = start_time.elapsed().as_secs_f64() * 1000.0;
-
-        let runs = motion_matching_result.matching_result.runs + 1;
-
-        motion_matching_result.matching_result.avg_time =
-            (motion_matching_result.matching_result.avg_time
-                * motion_matching_result.matching_result.runs as f64
-                + traj_duration)
-                / runs as f64;
-        motion_matching_result.matching_result.avg_memory =
-            (motion_matching_result.matching_result.avg_memory
-                * motion_matching_result.matching_result.runs as f64
-                + knn_search_peak_memory as f64)
-                / runs as f64;
-        motion_matching_result.matching_result.runs = runs;
-
-        nearest_trajectories_evw.send(NearestTrajectories {
-            trajectories: nearest_trajs,
-            entity,
-        });
-    }
+            .get_pose(pose_data, motion_asset.joints())?;
+            let (pos, rot) = pose.get_pos_rot(0);
+
+            Some(Mat4::from_rotation_translation(
+                rot,
+                pos + loop_offset * loop_count,
+            ))
+        })
+        .collect()
 }
 
 fn pose_match(
     motion_data: MotionData,
     q_transforms: Query<&Transform>,
     q_joint_maps: Query<&JointMap>,
+    mut q_players: Query<(
+        &TrajectoryPoseStack,
+        &Trajectory,
+        Option<&mut MatchHistory>,
+        Option<&ActionClipState>,
+    )>,
+    match_config: Res<MatchConfig>,
+    trajectory_config: Res<TrajectoryConfig>,
+    time: Res<Time>,
     mut nearest_trajectories_evr: EventReader<NearestTrajectories>,
     mut motion_matching_result: ResMut<MotionMatchingResult>,
     mut jump_evw: EventWriter<JumpToPose>,
@@ -332,28 +345,69 @@ fn pose_match(
             continue;
         };
 
-        let mut smallest_dist = f32::MAX;
+        let Ok((traj_pose_stack, trajectory, mut match_history, action_clip_state)) =
+            q_players.get_mut(trajs.entity)
+        else {
+            continue;
+        };
+
+        // Do not override the action clip that has been started since the search.
+        if action_clip_state.is_some_and(ActionClipState::is_playing) {
+            continue;
+        }
+
+        // The animation that is currently playing.
+        let current_pose = traj_pose_stack
+            .target()
+            .map(|traj_pose| *traj_pose.motion_pose());
+
+        if let Some(match_history) = &mut match_history {
+            match_history.prune(time.elapsed_secs());
+        }
+
+        // Score the current animation as a baseline, as it may not be among the nearest trajectories.
+        let baseline = match (current_pose, q_transforms.get(trajs.entity)) {
+            (Some(current_pose), Ok(transform)) => continuation_trajectory(
+                motion_asset,
+                &trajectory_config,
+                &current_pose,
+                &trajectory_offsets(trajectory, transform),
+            )
+            .filter(|baseline| {
+                !trajs.iter().any(|traj| {
+                    traj.chunk_index == baseline.chunk_index
+                        && traj.chunk_offset == baseline.chunk_offset
+                })
+            }),
+            _ => None,
+        };
+
+        let mut smallest_dist = f32::INFINITY;
         let mut best_traj_index = 0;
+        // Smallest distance and index of candidates that continue the current animation.
+        let mut best_continuation = None::<(f32, usize)>;
+
+        for traj in trajs.iter().chain(baseline.iter()) {
+            let traj_time = motion_asset
+                .trajectory_data
+                .time_from_chunk_offset(traj.chunk_offset);
 
-        for (i, traj) in trajs.iter().enumerate() {
             // Get pose based on trajectory data.
-            // TODO: check for looping. (UNSAFE!)
-            let pose = motion_asset
-                .pose_data
-                .get_chunk(traj.chunk_index)
-                .and_then(|poses| poses.get(traj.chunk_offset))
-                .unwrap();
+            let Some(pose) = candidate_pose(motion_asset, traj.chunk_index, traj_time) else {
+                continue;
+            };
+            let i = motion_matching_result.trajectories_poses.len();
 
             let mut pose_dist = 0.0;
 
-            for joint_info in motion_asset.joints() {
+            for (joint_index, joint_info) in motion_asset.joints().iter().enumerate() {
                 let joint_name = joint_info.name();
 
                 if let Some(transform) = joint_map
                     .get(joint_name)
                     .and_then(|e| q_transforms.get(*e).ok())
                 {
-                    let (pose_pos, pose_rot) = pose.get_pos_rot(joint_info);
+                    let (pose_pos, pose_rot) = pose.get_pos_rot(joint_index);
 
                     // Calcualte distance and angle difference.
                     pose_dist += Vec3::distance(transform.translation, pose_pos);
@@ -363,7 +417,38 @@ fn pose_match(
             pose_dist /= motion_asset.joints().len() as f32;
             pose_dist *= BVH_SCALE_RATIO;
 
-            let dist = pose_dist + traj.distance;
+            let mut dist = pose_dist + traj.distance;
+            let mut cooldown_penalty = 0.0;
+
+            let is_continuation = current_pose.as_ref().is_some_and(|current_pose| {
+                match_config.is_continuation(
+                    &motion_asset.pose_data,
+                    current_pose,
+                    traj.chunk_index,
+                    traj_time,
+                )
+            });
+
+            // Continuing the current animation is never penalized.
+            if let (Some(match_history), false) = (&match_history, is_continuation) {
+                cooldown_penalty =
+                    match_history.penalty(traj.chunk_index, traj_time, &match_config);
+                dist += cooldown_penalty;
+            }
+
+            if let Some(current_pose) = &current_pose {
+                dist += match_config.transition_cost(current_pose.chunk_index, traj.chunk_index);
+
+                if is_continuation {
+                    dist -= match_config.continuation_bonus;
+
+                    if best_continuation
+                        .is_none_or(|(continuation_dist, _)| dist < continuation_dist)
+                    {
+                        best_continuation = Some((dist, i));
+                    }
+                }
+            }
 
             if dist < smallest_dist {
                 smallest_dist = dist;
@@ -372,28 +457,260 @@ fn pose_match(
 
             motion_matching_result
                 .trajectories_poses
-                .push((*traj, pose_dist));
+                .push((*traj, pose_dist, cooldown_penalty));
+        }
+
+        // Only switch to another animation if it is noticeably better.
+        if let Some((continuation_dist, continuation_index)) = best_continuation {
+            if continuation_dist - smallest_dist < match_config.min_switch_improvement {
+                best_traj_index = continuation_index;
+            }
         }
 
         motion_matching_result.selected_trajectory = best_traj_index;
 
-        let best_traj = &trajs[best_traj_index];
+        // Every candidate is forbidden.
+        if smallest_dist.is_infinite() && best_continuation.is_none() {
+            continue;
+        }
+
+        let best_traj = &motion_matching_result.trajectories_poses[best_traj_index].0;
+        let motion_pose = MotionPose {
+            chunk_index: best_traj.chunk_index,
+            time: motion_asset
+                .trajectory_data
+                .time_from_chunk_offset(best_traj.chunk_offset),
+        };
+
+        if let Some(match_history) = &mut match_history {
+            match_history.record_selection(
+                current_pose.as_ref(),
+                &motion_pose,
+                best_continuation.is_some_and(|(_, index)| index == best_traj_index),
+                time.elapsed_secs(),
+                &match_config,
+            );
+        }
+
         jump_evw.send(JumpToPose {
-            motion_pose: MotionPose {
-                chunk_index: best_traj.chunk_index,
-                time: motion_asset
-                    .trajectory_data
-                    .time_from_chunk_offset(best_traj.chunk_offset),
-            },
+            motion_pose,
             entity: trajs.entity,
         });
     }
 }
 
+/// Pose at `time` inside `chunk_index`, looping the time for loopable chunks.
+fn candidate_pose(motion_asset: &MotionAsset, chunk_index: usize, time: f32) -> Option<JointPose> {
+    let pose_data = &motion_asset.pose_data;
+
+    let time = match pose_data.is_chunk_loopable(chunk_index)? {
+        true => time % pose_data.chunk_duration(chunk_index)?,
+        false => time,
+    };
+
+    MotionPose { chunk_index, time }.get_pose(pose_data, motion_asset.joints())
+}
+
+/// Trajectory that continues playing the current [`MotionPose`], with its distance to
+/// `traj_offsets`.
+///
+/// Returns [`None`] if the chunk does not hold a full trajectory at the current time.
+fn continuation_trajectory(
+    motion_asset: &MotionAsset,
+    trajectory_config: &TrajectoryConfig,
+    current_pose: &MotionPose,
+    traj_offsets: &[f32],
+) -> Option<MatchTrajectory> {
+    let pose_data = &motion_asset.pose_data;
+    let trajectory_data = &motion_asset.trajectory_data;
+
+    let chunk_index = current_pose.chunk_index;
+    let time = match pose_data.is_chunk_loopable(chunk_index)? {
+        true => current_pose.time % pose_data.chunk_duration(chunk_index)?,
+        false => current_pose.time,
+    };
+
+    let chunk = trajectory_data.get_chunk(chunk_index)?;
+    let chunk_offset = trajectory_data.chunk_offset_from_time(time);
+    if chunk_offset + trajectory_config.num_points() > chunk.len() {
+        return None;
+    }
+
+    let offsets = chunk_data_offsets(chunk, chunk_offset, trajectory_config);
+
+    Some(MatchTrajectory {
+        distance: offset_distance(traj_offsets, &offsets),
+        chunk_index,
+        chunk_offset,
+    })
+}
+
+/// Time (in seconds) after which [`flow`] matches the prediction of the playing animation,
+/// leaving enough of the prediction to blend into the next animation.
+pub fn prediction_search_interval(
+    trajectory_config: &TrajectoryConfig,
+    motion_player_config: &MotionPlayerConfig,
+) -> f32 {
+    trajectory_config.predict_time() - motion_player_config.interp_duration()
+}
+
+/// Minimum duration (in seconds) a candidate must be able to play for:
+/// until the next search (`search_interval`) plus the time to blend into the next animation.
+pub fn min_playable_duration(
+    search_interval: f32,
+    motion_player_config: &MotionPlayerConfig,
+) -> f32 {
+    search_interval + motion_player_config.interp_duration()
+}
+
+/// Last chunk offset of each chunk that can still be played for a minimum duration.
+///
+/// Loopable chunks can always be played.
+#[derive(Debug, Clone)]
+pub struct PlayableOffsets(Vec<Option<usize>>);
+
+impl PlayableOffsets {
+    pub fn new(motion_asset: &MotionAsset, min_duration: f32) -> Self {
+        let pose_data = &motion_asset.pose_data;
+
+        let max_chunk_offsets = (0..pose_data.offsets().num_chunks())
+            .map(|chunk_index| {
+                if pose_data.is_chunk_loopable(chunk_index)? {
+                    return Some(usize::MAX);
+                }
+
+                let latest_time = pose_data.chunk_duration(chunk_index)? - min_duration;
+                (latest_time >= 0.0).then(|| {
+                    motion_asset
+                        .trajectory_data
+                        .chunk_offset_from_time(latest_time)
+                })
+            })
+            .collect();
+
+        Self(max_chunk_offsets)
+    }
+
+    /// Can the trajectory at `chunk_offset` inside `chunk_index` be played?
+    pub fn contains(&self, chunk_index: usize, chunk_offset: usize) -> bool {
+        self.0
+            .get(chunk_index)
+            .copied()
+            .flatten()
+            .is_some_and(|max_chunk_offset| chunk_offset <= max_chunk_offset)
+    }
+}
+
+/// Flattened trajectory offsets (`[x0, y0, x1, y1, ...]`) of every trajectory inside the
+/// [`MotionAsset`], relative to the trajectory's current point and scaled by [`BVH_SCALE_RATIO`].
+///
+/// Yields `(chunk_index, chunk_offset, offsets)`.
+pub fn iter_data_offsets<'a>(
+    motion_asset: &'a MotionAsset,
+    trajectory_config: &'a TrajectoryConfig,
+) -> impl Iterator<Item = (usize, usize, Vec<f32>)> + 'a {
+    let num_segments = trajectory_config.num_segments();
+
+    motion_asset
+        .trajectory_data
+        .iter_chunk()
+        .enumerate()
+        .flat_map(move |(chunk_index, chunk)| {
+            // Number of trajectory in this chunk.
+            let num_trajectories = chunk.len().saturating_sub(num_segments);
+
+            (0..num_trajectories).map(move |chunk_offset| {
+                (
+                    chunk_index,
+                    chunk_offset,
+                    chunk_data_offsets(chunk, chunk_offset, trajectory_config),
+                )
+            })
+        })
+}
+
+/// Flattened trajectory offsets of the trajectory starting at `chunk_offset` inside `chunk`.
+///
+/// See [`iter_data_offsets`].
+fn chunk_data_offsets(
+    chunk: &[TrajectoryDataPoint],
+    chunk_offset: usize,
+    trajectory_config: &TrajectoryConfig,
+) -> Vec<f32> {
+    let data_traj = &chunk[chunk_offset..chunk_offset + trajectory_config.num_points()];
+
+    // Center point of trajectory
+    let data_inv_matrix = data_traj[trajectory_config.history_count].matrix.inverse();
+
+    let data_traj = data_traj
+        .iter()
+        .map(|point| {
+            let (.., translation) = point.matrix.to_scale_rotation_translation();
+            data_inv_matrix.transform_point3(translation).xz()
+        })
+        .collect::<Vec<_>>();
+
+    let mut offsets = Vec::with_capacity(trajectory_config.num_segments() * 2);
+    for i in 1..data_traj.len() {
+        let offset = (data_traj[i] - data_traj[i - 1]) * BVH_SCALE_RATIO;
+        offsets.push(offset.x);
+        offsets.push(offset.y);
+    }
+
+    offsets
+}
+
+/// Flattened trajectory offsets (`[x0, y0, x1, y1, ...]`) of a [`Trajectory`], relative to
+/// the entity's [`Transform`].
+pub fn trajectory_offsets(trajectory: &Trajectory, transform: &Transform) -> Vec<f32> {
+    let inv_matrix = transform.compute_matrix().inverse();
+    let traj = trajectory
+        .iter()
+        .map(|point| {
+            inv_matrix
+                .transform_point3(Vec3::new(point.translation.x, 0.0, point.translation.y))
+                .xz()
+        })
+        .collect::<Vec<_>>();
+
+    let mut traj_offsets = Vec::with_capacity(traj.len().saturating_sub(1) * 2);
+    for i in 1..traj.len() {
+        let offset = traj[i] - traj[i - 1];
+        traj_offsets.push(offset.x);
+        traj_offsets.push(offset.y);
+    }
+
+    traj_offsets
+}
+
+/// Distance between 2 flattened trajectory offsets (`[x0, y0, x1, y1, ...]`).
+///
+/// Equivalent to [`TrajectoryDistance`] on the trajectory points that produced the offsets,
+/// so that every matching method ranks and thresholds in the same metric.
+pub fn offset_distance(offsets0: &[f32], offsets1: &[f32]) -> f32 {
+    let len = offsets0.len();
+    debug_assert_eq!(len, offsets1.len());
+
+    let mut offset_distance = 0.0;
+
+    for i in 0..len / 2 {
+        let x_index = i * 2;
+        let y_index = x_index + 1;
+
+        let offset0 = Vec2::new(offsets0[x_index], offsets0[y_index]);
+        let offset1 = Vec2::new(offsets1[x_index], offsets1[y_index]);
+
+        offset_distance += offset0.distance(offset1);
+    }
+
+    // Averaging the distances over the number of segments.
+    offset_distance /= (len / 2) as f32;
+    offset_distance
+}
+
 #[derive(Event, Debug, Deref, DerefMut)]
 pub struct TrajectoryMatch(pub Entity);
 
-// TODO: Prediction match must loop back for loopable animations.
 #[derive(Event, Debug, Deref, DerefMut)]
 pub struct PredictionMatch {
     #[deref]
@@ -434,10 +751,66 @@ pub struct MatchConfig {
     /// Any distance beyond this threshold will not be considered.
     pub match_threshold: f32,
     pub pred_match_threshold: f32,
+    /// Distance deducted from candidates that continue the currently playing animation.
+    pub continuation_bonus: f32,
+    /// Maximum time difference (in seconds) from the current playing time
+    /// for a candidate in the same chunk to count as a continuation.
+    pub continuation_window: f32,
+    /// Minimum distance improvement over the best continuation required to switch animation.
+    pub min_switch_improvement: f32,
+    /// Additional distance for switching from one chunk index to another `(from, to)`.
+    pub transition_costs: HashMap<(usize, usize), f32>,
+    /// Duration (in seconds) for a played segment to stay in the [`MatchHistory`].
+    pub cooldown_duration: f32,
+    /// Time (in seconds) around recently played segments that gets penalized.
+    pub cooldown_window: f32,
+    /// Distance added to candidates near recently played segments.
+    /// Set to [`f32::INFINITY`] to forbid them entirely.
+    pub cooldown_penalty: f32,
+}
+
+impl MatchConfig {
+    /// Does a candidate at `time` in `chunk_index` continue the current [`MotionPose`]?
+    ///
+    /// Times of loopable chunks are compared across the loop seam.
+    pub fn is_continuation(
+        &self,
+        pose_data: &PoseData,
+        current: &MotionPose,
+        chunk_index: usize,
+        time: f32,
+    ) -> bool {
+        if current.chunk_index != chunk_index {
+            return false;
+        }
+
+        let time_difference = match (
+            pose_data.is_chunk_loopable(chunk_index),
+            pose_data.chunk_duration(chunk_index),
+        ) {
+            (Some(true), Some(duration)) if duration > 0.0 => {
+                let difference = (time - current.time).rem_euclid(duration);
+                f32::min(difference, duration - difference)
+            }
+            _ => f32::abs(time - current.time),
+        };
+
+        time_difference <= self.continuation_window
+    }
+
+    /// Additional distance for switching from one chunk index to another.
+    pub fn transition_cost(&self, from_chunk_index: usize, to_chunk_index: usize) -> f32 {
+        self.transition_costs
+            .get(&(from_chunk_index, to_chunk_index))
+            .copied()
+            .unwrap_or_default()
+    }
 }
 
 #[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MotionMatchingSet {
+    /// Starts and finishes action clips.
+    Action,
     Flow,
     PredictionMatch,
     GlobalMatch,
@@ -0,0 +1,195 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::motion::motion_asset::MotionAsset;
+use crate::motion::motion_player::{
+    JumpToPose, MotionPlayer, MotionPlayerConfig, MotionPose, TrajectoryPoseStack,
+};
+use crate::motion::MotionData;
+use crate::MotionUpdate;
+
+use super::{MotionMatchingSet, TrajectoryMatch};
+
+pub struct ActionClipPlugin;
+
+impl Plugin for ActionClipPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ActionClipLibrary>()
+            .add_event::<PlayActionClip>()
+            .add_systems(PreUpdate, init_action_clip_state)
+            .add_systems(
+                MotionUpdate,
+                (finish_action_clips, start_action_clips)
+                    .chain()
+                    .in_set(MotionMatchingSet::Action),
+            );
+    }
+}
+
+fn init_action_clip_state(mut commands: Commands, q_players: Query<Entity, Added<MotionPlayer>>) {
+    for entity in q_players.iter() {
+        commands.entity(entity).insert(ActionClipState::default());
+    }
+}
+
+/// Hand back to motion matching once an action clip is about to end,
+/// leaving enough time to blend into the best continuation pose.
+fn finish_action_clips(
+    motion_data: MotionData,
+    mut q_players: Query<(&mut ActionClipState, &TrajectoryPoseStack, Entity)>,
+    motion_player_config: Res<MotionPlayerConfig>,
+    mut traj_match_evw: EventWriter<TrajectoryMatch>,
+) {
+    let Some(motion_asset) = motion_data.get() else {
+        return;
+    };
+
+    for (mut action_clip_state, traj_pose_stack, entity) in q_players.iter_mut() {
+        let ActionClipState::Playing { clip, .. } = &*action_clip_state else {
+            continue;
+        };
+
+        let current_pose = traj_pose_stack
+            .target()
+            .map(|traj_pose| *traj_pose.motion_pose());
+
+        let finished = match current_pose {
+            // Interrupted by another animation.
+            Some(current_pose) if current_pose.chunk_index != clip.chunk_index => true,
+            Some(current_pose) => clip.end_time(motion_asset).is_none_or(|end_time| {
+                current_pose.time >= end_time - motion_player_config.interp_duration()
+            }),
+            None => true,
+        };
+
+        if finished {
+            *action_clip_state = ActionClipState::Locomotion;
+            // Search for the best pose to continue from.
+            traj_match_evw.send(TrajectoryMatch(entity));
+        }
+    }
+}
+
+/// Handle the [`PlayActionClip`] event.
+fn start_action_clips(
+    motion_data: MotionData,
+    mut q_players: Query<&mut ActionClipState>,
+    library: Res<ActionClipLibrary>,
+    mut play_evr: EventReader<PlayActionClip>,
+    mut jump_evw: EventWriter<JumpToPose>,
+) {
+    let Some(motion_asset) = motion_data.get() else {
+        return;
+    };
+
+    for play in play_evr.read() {
+        let Ok(mut action_clip_state) = q_players.get_mut(play.entity) else {
+            continue;
+        };
+
+        let Some(clip) = library.resolve(&play.name, motion_asset) else {
+            warn!("Action clip \"{}\" does not exist.", play.name);
+            continue;
+        };
+
+        jump_evw.send(JumpToPose {
+            motion_pose: MotionPose {
+                chunk_index: clip.chunk_index,
+                time: clip.start_time,
+            },
+            entity: play.entity,
+        });
+
+        *action_clip_state = ActionClipState::Playing {
+            name: play.name.clone(),
+            clip,
+        };
+    }
+}
+
+/// Play a named clip on a motion player, suspending motion matching until it ends.
+#[derive(Event, Debug, Clone)]
+pub struct PlayActionClip {
+    pub entity: Entity,
+    /// Name inside the [`ActionClipLibrary`] or the name of an animation file.
+    pub name: String,
+}
+
+/// Action clip a motion player is playing instead of searching for locomotion.
+#[derive(Component, Default, Debug, Clone)]
+pub enum ActionClipState {
+    /// Driven by motion matching.
+    #[default]
+    Locomotion,
+    Playing {
+        name: String,
+        clip: ActionClip,
+    },
+}
+
+impl ActionClipState {
+    pub fn is_playing(&self) -> bool {
+        matches!(self, Self::Playing { .. })
+    }
+}
+
+/// A time range inside a chunk of the motion data.
+#[derive(Debug, Clone, Copy)]
+pub struct ActionClip {
+    pub chunk_index: usize,
+    /// Time in seconds the clip starts at.
+    pub start_time: f32,
+    /// Time in seconds the clip ends at, the end of the chunk if [`None`].
+    pub end_time: Option<f32>,
+}
+
+impl ActionClip {
+    /// The whole chunk.
+    pub fn from_chunk(chunk_index: usize) -> Self {
+        Self {
+            chunk_index,
+            start_time: 0.0,
+            end_time: None,
+        }
+    }
+
+    /// Time in seconds the clip ends at.
+    pub fn end_time(&self, motion_asset: &MotionAsset) -> Option<f32> {
+        let duration = motion_asset.pose_data.chunk_duration(self.chunk_index)?;
+        Some(
+            self.end_time
+                .map_or(duration, |end_time| end_time.min(duration)),
+        )
+    }
+}
+
+/// Named [`ActionClip`]s, e.g. a jump inside a longer recording.
+///
+/// Animation files can also be played by name without being added here.
+#[derive(Resource, Default, Debug)]
+pub struct ActionClipLibrary(HashMap<String, ActionClip>);
+
+impl ActionClipLibrary {
+    pub fn insert(&mut self, name: &str, clip: ActionClip) {
+        self.0.insert(name.to_string(), clip);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<ActionClip> {
+        self.0.remove(name)
+    }
+
+    /// Find a clip by name, falling back to the whole chunk of an animation file with that name.
+    pub fn resolve(&self, name: &str, motion_asset: &MotionAsset) -> Option<ActionClip> {
+        self.0.get(name).copied().or_else(|| {
+            motion_asset
+                .animation_file
+                .iter()
+                .position(|file| file == name)
+                .map(ActionClip::from_chunk)
+        })
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+}
@@ -0,0 +1,700 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::hash::Hasher;
+use std::time::Instant;
+
+use bevy::prelude::*;
+use bevy::tasks::{ComputeTaskPool, TaskPool};
+
+use crate::motion::chunk::ChunkIterator;
+use crate::motion::motion_asset::MotionAsset;
+use crate::motion::motion_player::MotionPlayerConfig;
+use crate::motion::MotionData;
+use crate::trajectory::{Trajectory, TrajectoryConfig, TrajectoryDistance, TrajectoryPoint};
+use crate::ui::play_mode::MotionMatchingResult;
+use crate::{Method, MotionUpdate, BVH_SCALE_RATIO};
+
+use super::search_index::hash_motion_source;
+use super::search_schedule::SearchSchedule;
+use super::{
+    iter_data_offsets, min_playable_duration, prediction_search_interval, MatchConfig,
+    MatchTrajectory, MotionMatchingSet, NearestTrajectories, PlayableOffsets, TrajectoryMatch,
+    PEAK_ALLOC,
+};
+
+/// Number of trajectories that are compared at once.
+const LANES: usize = 8;
+/// Minimum number of blocks scanned by a single task.
+const MIN_BLOCKS_PER_TASK: usize = 64;
+
+pub struct BruteForceMatchPlugin;
+
+impl Plugin for BruteForceMatchPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BruteForceConfig>()
+            .add_systems(
+                PreUpdate,
+                (
+                    invalidate_trajectory_features,
+                    populate_trajectory_features.run_if(not(resource_exists::<TrajectoryFeatures>)),
+                )
+                    .chain(),
+            )
+            .add_systems(
+                MotionUpdate,
+                trajectory_match
+                    .in_set(MotionMatchingSet::GlobalMatch)
+                    .run_if(resource_exists::<TrajectoryFeatures>)
+                    .run_if(in_state(Method::BruteForceKNN)),
+            );
+    }
+}
+
+fn populate_trajectory_features(
+    mut commands: Commands,
+    motion_data: MotionData,
+    trajectory_config: Res<TrajectoryConfig>,
+) {
+    let Some(motion_asset) = motion_data.get() else {
+        return;
+    };
+
+    commands.insert_resource(TrajectoryFeatures::build(motion_asset, &trajectory_config));
+}
+
+/// Remove the [`TrajectoryFeatures`] whenever the motion data is modified, or when a
+/// [`TrajectoryConfig`] change alters its source hash, so that it can be populated again.
+fn invalidate_trajectory_features(
+    mut commands: Commands,
+    features: Option<Res<TrajectoryFeatures>>,
+    motion_data: MotionData,
+    trajectory_config: Res<TrajectoryConfig>,
+    mut motion_asset_evr: EventReader<AssetEvent<MotionAsset>>,
+) {
+    let mut modified = motion_asset_evr
+        .read()
+        .any(|event| matches!(event, AssetEvent::Modified { .. }));
+
+    if trajectory_config.is_changed() {
+        if let (Some(features), Some(motion_asset)) = (&features, motion_data.get()) {
+            modified |= hash_motion_source(motion_asset, &trajectory_config).finish()
+                != features.source_hash;
+        }
+    }
+
+    if modified {
+        commands.remove_resource::<TrajectoryFeatures>();
+    }
+}
+
+/// Search for the best match trajectory from [`TrajectoryFeatures`].
+///
+/// Performs a match every [`TrajectoryMatch`] event.
+fn trajectory_match(
+    motion_data: MotionData,
+    q_trajectory: Query<(&Trajectory, &Transform, Option<&SearchSchedule>)>,
+    trajectory_config: Res<TrajectoryConfig>,
+    motion_player_config: Res<MotionPlayerConfig>,
+    match_config: Res<MatchConfig>,
+    brute_force_config: Res<BruteForceConfig>,
+    features: Res<TrajectoryFeatures>,
+    mut motion_matching_result: ResMut<MotionMatchingResult>,
+    mut match_evr: EventReader<TrajectoryMatch>,
+    mut nearest_trajectories_evw: EventWriter<NearestTrajectories>,
+) {
+    // println!("Brute Force KNN Method");
+    PEAK_ALLOC.reset_peak_usage();
+    let Some(motion_asset) = motion_data.get() else {
+        return;
+    };
+
+    let prediction_interval = prediction_search_interval(&trajectory_config, &motion_player_config);
+
+    for traj_match in match_evr.read() {
+        let entity = **traj_match;
+        let Ok((traj, transform, search_schedule)) = q_trajectory.get(entity) else {
+            continue;
+        };
+
+        // Candidates must keep playing until the next search of this entity.
+        let search_interval = search_schedule.map_or(prediction_interval, |search_schedule| {
+            search_schedule.search_interval(prediction_interval)
+        });
+        let playable_offsets = PlayableOffsets::new(
+            motion_asset,
+            min_playable_duration(search_interval, &motion_player_config),
+        );
+
+        let inv_matrix = transform.compute_matrix().inverse();
+        let traj = traj
+            .iter()
+            .map(|&(mut point)| {
+                point.translation = inv_matrix
+                    .transform_point3(Vec3::new(point.translation.x, 0.0, point.translation.y))
+                    .xz();
+                point
+            })
+            .collect::<Vec<_>>();
+
+        let mut traj_offsets = Vec::new();
+        // Create trajectory offset.
+        for i in 1..traj.len() {
+            let offset = traj[i].translation - traj[i - 1].translation;
+            traj_offsets.push(offset.x);
+            traj_offsets.push(offset.y);
+        }
+
+        let start_time = Instant::now();
+
+        let nearest_trajs = features.nearest(
+            &traj_offsets,
+            match_config.max_match_count,
+            match_config.match_threshold,
+            &playable_offsets,
+        );
+
+        let knn_search_peak_memory = PEAK_ALLOC.peak_usage_as_mb();
+        let traj_duration = start_time.elapsed().as_secs_f64() * 1000.0;
+
+        let result = &mut motion_matching_result.matching_result;
+        let runs = result.runs + 1;
+
+        result.avg_time = (result.avg_time * result.runs as f64 + traj_duration) / runs as f64;
+        result.avg_memory =
+            (result.avg_memory * result.runs as f64 + knn_search_peak_memory as f64) / runs as f64;
+        result.runs = runs;
+
+        if brute_force_config.benchmark {
+            let start_time = Instant::now();
+
+            reference_nearest(
+                motion_asset,
+                &traj,
+                &trajectory_config,
+                match_config.max_match_count,
+                match_config.match_threshold,
+                &playable_offsets,
+            );
+
+            let reference_duration = start_time.elapsed().as_secs_f64() * 1000.0;
+
+            let reference_runs = result.reference_runs + 1;
+            result.avg_reference_time = (result.avg_reference_time * result.reference_runs as f64
+                + reference_duration)
+                / reference_runs as f64;
+            result.reference_runs = reference_runs;
+        }
+
+        nearest_trajectories_evw.send(NearestTrajectories {
+            trajectories: nearest_trajs,
+            entity,
+        });
+    }
+}
+
+/// Single threaded scan that builds every trajectory from the [`MotionAsset`] on the fly.
+///
+/// Only used as a reference to benchmark [`TrajectoryFeatures::nearest`] against.
+fn reference_nearest(
+    motion_asset: &MotionAsset,
+    traj: &[TrajectoryPoint],
+    trajectory_config: &TrajectoryConfig,
+    max_match_count: usize,
+    match_threshold: f32,
+    playable_offsets: &PlayableOffsets,
+) -> Vec<MatchTrajectory> {
+    let num_segments = trajectory_config.num_segments();
+    let num_points = trajectory_config.num_points();
+
+    let mut nearest_trajs = BoundedHeap::new(max_match_count);
+
+    for (chunk_index, chunk) in motion_asset.trajectory_data.iter_chunk().enumerate() {
+        // Number of trajectory in this chunk.
+        let num_trajectories = chunk.len().saturating_sub(num_segments);
+
+        for chunk_offset in 0..num_trajectories {
+            if !playable_offsets.contains(chunk_index, chunk_offset) {
+                continue;
+            }
+
+            let data_traj = &chunk[chunk_offset..chunk_offset + num_points];
+
+            // Center point of trajectory
+            let data_inv_matrix = data_traj[trajectory_config.history_count].matrix.inverse();
+
+            let data_traj = data_traj
+                .iter()
+                .map(|point| {
+                    let (.., translation) = point.matrix.to_scale_rotation_translation();
+                    TrajectoryPoint {
+                        translation: data_inv_matrix.transform_point3(translation).xz()
+                            * BVH_SCALE_RATIO,
+                        velocity: point.velocity * BVH_SCALE_RATIO,
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            let distance = traj.distance(&data_traj);
+
+            // Distance must be below the threshold.
+            if distance > match_threshold {
+                continue;
+            }
+
+            nearest_trajs.push(MatchTrajectory {
+                distance,
+                chunk_index,
+                chunk_offset,
+            });
+        }
+    }
+
+    nearest_trajs.into_sorted_vec()
+}
+
+#[derive(Resource, Default, Debug, Clone, Copy)]
+pub struct BruteForceConfig {
+    /// Also run the single threaded reference scan on every search to measure the speed-up.
+    ///
+    /// On 118 800 synthetic trajectories (release build, single core), a search takes 0.91 ms
+    /// against 10.0 ms for the reference scan (11x). See `features_speed_up_over_reference`.
+    pub benchmark: bool,
+}
+
+/// Trajectory offsets of a [`MotionAsset`] laid out for fast linear scans.
+///
+/// Trajectories are grouped into blocks of [`LANES`]. Within a block, each segment stores
+/// the x offsets of all lanes followed by the y offsets of all lanes, so that distances of
+/// a whole block can be computed with SIMD.
+#[derive(Resource, Debug)]
+pub struct TrajectoryFeatures {
+    /// Hash of the source data these features were built from.
+    source_hash: u64,
+    num_segments: usize,
+    /// Blocks of `num_segments * 2 * LANES` offsets.
+    blocks: Vec<f32>,
+    /// `(chunk_index, chunk_offset)` of each trajectory.
+    entries: Vec<(usize, usize)>,
+}
+
+impl TrajectoryFeatures {
+    pub fn build(motion_asset: &MotionAsset, trajectory_config: &TrajectoryConfig) -> Self {
+        let num_segments = trajectory_config.num_segments();
+        let block_len = num_segments * 2 * LANES;
+
+        let mut blocks = Vec::new();
+        let mut entries = Vec::new();
+
+        for (chunk_index, chunk_offset, offsets) in
+            iter_data_offsets(motion_asset, trajectory_config)
+        {
+            let lane = entries.len() % LANES;
+            if lane == 0 {
+                // Unused lanes never pass the match threshold.
+                blocks.resize(blocks.len() + block_len, f32::INFINITY);
+            }
+
+            let block_start = blocks.len() - block_len;
+            for (segment, offset) in offsets.chunks_exact(2).enumerate() {
+                let segment_start = block_start + segment * 2 * LANES;
+                blocks[segment_start + lane] = offset[0];
+                blocks[segment_start + LANES + lane] = offset[1];
+            }
+
+            entries.push((chunk_index, chunk_offset));
+        }
+
+        Self {
+            source_hash: hash_motion_source(motion_asset, trajectory_config).finish(),
+            num_segments,
+            blocks,
+            entries,
+        }
+    }
+
+    /// Search for the nearest playable trajectories (sorted by distance).
+    ///
+    /// Blocks are split across the [`ComputeTaskPool`], each task keeping its own bounded heap
+    /// which are merged at the end.
+    pub fn nearest(
+        &self,
+        traj_offsets: &[f32],
+        max_match_count: usize,
+        match_threshold: f32,
+        playable_offsets: &PlayableOffsets,
+    ) -> Vec<MatchTrajectory> {
+        if max_match_count == 0 || self.entries.is_empty() {
+            return Vec::new();
+        }
+        debug_assert_eq!(traj_offsets.len(), self.num_segments * 2);
+
+        let block_len = self.num_segments * 2 * LANES;
+        let num_blocks = self.blocks.len() / block_len;
+
+        let task_pool = ComputeTaskPool::get_or_init(TaskPool::default);
+        let blocks_per_task = usize::max(
+            num_blocks.div_ceil(task_pool.thread_num().max(1)),
+            MIN_BLOCKS_PER_TASK,
+        );
+
+        let task_heaps = task_pool.scope(|scope| {
+            for (task_index, task_blocks) in
+                self.blocks.chunks(block_len * blocks_per_task).enumerate()
+            {
+                scope.spawn(async move {
+                    let mut heap = BoundedHeap::new(max_match_count);
+                    let first_entry = task_index * blocks_per_task * LANES;
+
+                    for (block_index, block) in task_blocks.chunks_exact(block_len).enumerate() {
+                        let distances = self.block_distances(block, traj_offsets);
+
+                        for (lane, distance) in distances.into_iter().enumerate() {
+                            // Distance must be below the threshold.
+                            if distance > match_threshold {
+                                continue;
+                            }
+
+                            let Some(&(chunk_index, chunk_offset)) =
+                                self.entries.get(first_entry + block_index * LANES + lane)
+                            else {
+                                continue;
+                            };
+                            if !playable_offsets.contains(chunk_index, chunk_offset) {
+                                continue;
+                            }
+                            heap.push(MatchTrajectory {
+                                distance,
+                                chunk_index,
+                                chunk_offset,
+                            });
+                        }
+                    }
+
+                    heap
+                });
+            }
+        });
+
+        let mut nearest_trajs = BoundedHeap::new(max_match_count);
+        for task_heap in task_heaps {
+            for HeapMatch(match_traj) in task_heap.heap {
+                nearest_trajs.push(match_traj);
+            }
+        }
+
+        nearest_trajs.into_sorted_vec()
+    }
+
+    /// Distances from the trajectory offsets to all trajectories inside a block.
+    #[inline]
+    fn block_distances(&self, block: &[f32], traj_offsets: &[f32]) -> [f32; LANES] {
+        let mut distances = [0.0; LANES];
+
+        for (segment, offset) in block
+            .chunks_exact(2 * LANES)
+            .zip(traj_offsets.chunks_exact(2))
+        {
+            let (xs, ys) = segment.split_at(LANES);
+
+            for lane in 0..LANES {
+                let dx = xs[lane] - offset[0];
+                let dy = ys[lane] - offset[1];
+                distances[lane] += f32::sqrt(dx * dx + dy * dy);
+            }
+        }
+
+        // Averaging the distances over the number of segments.
+        let num_segments = self.num_segments as f32;
+        for distance in distances.iter_mut() {
+            *distance /= num_segments;
+        }
+
+        distances
+    }
+}
+
+/// Max heap that only keeps the `capacity` smallest [`MatchTrajectory`].
+pub(super) struct BoundedHeap {
+    capacity: usize,
+    heap: BinaryHeap<HeapMatch>,
+}
+
+impl BoundedHeap {
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            heap: BinaryHeap::with_capacity(capacity + 1),
+        }
+    }
+
+    pub(super) fn push(&mut self, match_traj: MatchTrajectory) {
+        let match_traj = HeapMatch(match_traj);
+
+        if self.heap.len() < self.capacity {
+            self.heap.push(match_traj);
+        } else if let Some(mut worst_match) = self.heap.peek_mut() {
+            if match_traj < *worst_match {
+                *worst_match = match_traj;
+            }
+        }
+    }
+
+    /// Largest distance kept once the heap is full.
+    pub(super) fn worst_distance(&self) -> Option<f32> {
+        match self.heap.len() < self.capacity {
+            true => None,
+            false => self
+                .heap
+                .peek()
+                .map(|HeapMatch(match_traj)| match_traj.distance),
+        }
+    }
+
+    pub(super) fn into_sorted_vec(self) -> Vec<MatchTrajectory> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|HeapMatch(match_traj)| match_traj)
+            .collect()
+    }
+}
+
+/// [`MatchTrajectory`] ordered by distance.
+///
+/// Ties are ordered by position in the motion data so that results do not depend
+/// on how the scan was split across tasks.
+struct HeapMatch(MatchTrajectory);
+
+impl Ord for HeapMatch {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .distance
+            .total_cmp(&other.0.distance)
+            .then(self.0.chunk_index.cmp(&other.0.chunk_index))
+            .then(self.0.chunk_offset.cmp(&other.0.chunk_offset))
+    }
+}
+
+impl PartialOrd for HeapMatch {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for HeapMatch {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapMatch {}
+
+#[cfg(test)]
+mod tests {
+    use crate::motion::trajectory_data::{TrajectoryDataConfig, TrajectoryDataPoint};
+
+    use super::*;
+
+    const TRAJECTORY_CONFIG: TrajectoryConfig = TrajectoryConfig {
+        interval_time: 0.1667,
+        predict_count: 5,
+        history_count: 1,
+    };
+
+    /// Wandering root paths, every other chunk being loopable.
+    fn synthetic_motion_asset(num_chunks: usize, chunk_len: usize) -> MotionAsset {
+        let config = TrajectoryDataConfig {
+            interval_time: TRAJECTORY_CONFIG.interval_time,
+            num_points: TRAJECTORY_CONFIG.num_points(),
+        };
+
+        let chunks = (0..num_chunks).map(|chunk_index| {
+            let mut translation = Vec3::ZERO;
+            let mut heading = chunk_index as f32;
+
+            let chunk = (0..chunk_len)
+                .map(|i| {
+                    let t = i as f32 * 0.35 + chunk_index as f32 * 1.7;
+                    heading += f32::sin(t) * 0.4;
+                    let speed = 100.0 + 60.0 * f32::sin(t * 0.5);
+                    let velocity = Vec2::from_angle(heading) * speed;
+                    translation += Vec3::new(velocity.x, 0.0, velocity.y) * config.interval_time;
+
+                    TrajectoryDataPoint {
+                        matrix: Mat4::from_rotation_translation(
+                            Quat::from_rotation_y(heading),
+                            translation,
+                        ),
+                        velocity,
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            (chunk, chunk_index % 2 == 1)
+        });
+
+        MotionAsset::from_trajectory_chunks(config, chunks)
+    }
+
+    /// A slightly bent copy of the trajectory at `chunk_offset` inside `chunk_index`.
+    fn query_trajectory(
+        motion_asset: &MotionAsset,
+        chunk_index: usize,
+        chunk_offset: usize,
+    ) -> Vec<TrajectoryPoint> {
+        let chunk = motion_asset.trajectory_data.get_chunk(chunk_index).unwrap();
+        let data_traj = &chunk[chunk_offset..chunk_offset + TRAJECTORY_CONFIG.num_points()];
+        let inv_matrix = data_traj[TRAJECTORY_CONFIG.history_count].matrix.inverse();
+
+        data_traj
+            .iter()
+            .enumerate()
+            .map(|(i, point)| {
+                let (.., translation) = point.matrix.to_scale_rotation_translation();
+                TrajectoryPoint {
+                    translation: inv_matrix.transform_point3(translation).xz() * BVH_SCALE_RATIO
+                        + Vec2::new(0.02, -0.01) * i as f32,
+                    velocity: point.velocity * BVH_SCALE_RATIO,
+                }
+            })
+            .collect()
+    }
+
+    fn offsets(traj: &[TrajectoryPoint]) -> Vec<f32> {
+        traj.windows(2)
+            .flat_map(|points| {
+                let offset = points[1].translation - points[0].translation;
+                [offset.x, offset.y]
+            })
+            .collect()
+    }
+
+    fn match_traj(distance: f32, chunk_index: usize, chunk_offset: usize) -> MatchTrajectory {
+        MatchTrajectory {
+            distance,
+            chunk_index,
+            chunk_offset,
+        }
+    }
+
+    #[test]
+    fn bounded_heap_keeps_the_smallest_in_order() {
+        let mut heap = BoundedHeap::new(3);
+        for (i, distance) in [0.5, 0.1, 0.9, 0.3, 0.7, 0.2].into_iter().enumerate() {
+            heap.push(match_traj(distance, 0, i));
+            if i < 2 {
+                assert_eq!(heap.worst_distance(), None);
+            }
+        }
+
+        assert_eq!(heap.worst_distance(), Some(0.3));
+        let distances = heap
+            .into_sorted_vec()
+            .iter()
+            .map(|match_traj| match_traj.distance)
+            .collect::<Vec<_>>();
+        assert_eq!(distances, [0.1, 0.2, 0.3]);
+    }
+
+    #[test]
+    fn bounded_heap_breaks_ties_by_position() {
+        let mut heap = BoundedHeap::new(2);
+        heap.push(match_traj(0.5, 2, 0));
+        heap.push(match_traj(0.5, 1, 7));
+        heap.push(match_traj(0.5, 1, 3));
+        heap.push(match_traj(0.5, 3, 0));
+
+        assert_eq!(
+            heap.into_sorted_vec(),
+            [match_traj(0.5, 1, 3), match_traj(0.5, 1, 7)]
+        );
+    }
+
+    #[test]
+    fn bounded_heap_without_capacity_stays_empty() {
+        let mut heap = BoundedHeap::new(0);
+        heap.push(match_traj(0.1, 0, 0));
+
+        assert!(heap.into_sorted_vec().is_empty());
+    }
+
+    #[test]
+    fn features_match_the_reference_scan() {
+        let motion_asset = synthetic_motion_asset(6, 90);
+        let features = TrajectoryFeatures::build(&motion_asset, &TRAJECTORY_CONFIG);
+        let playable_offsets = PlayableOffsets::new(&motion_asset, 1.0);
+
+        for (chunk_index, chunk_offset) in [(0, 3), (1, 40), (4, 80), (5, 12)] {
+            let traj = query_trajectory(&motion_asset, chunk_index, chunk_offset);
+            let traj_offsets = offsets(&traj);
+
+            for (max_match_count, match_threshold) in [(1, 0.3), (5, 0.3), (16, f32::MAX)] {
+                let nearest = features.nearest(
+                    &traj_offsets,
+                    max_match_count,
+                    match_threshold,
+                    &playable_offsets,
+                );
+                let reference = reference_nearest(
+                    &motion_asset,
+                    &traj,
+                    &TRAJECTORY_CONFIG,
+                    max_match_count,
+                    match_threshold,
+                    &playable_offsets,
+                );
+
+                assert!(!nearest.is_empty());
+                assert_eq!(nearest.len(), reference.len());
+                for (nearest, reference) in nearest.iter().zip(&reference) {
+                    assert_eq!(
+                        (nearest.chunk_index, nearest.chunk_offset),
+                        (reference.chunk_index, reference.chunk_offset)
+                    );
+                    assert!((nearest.distance - reference.distance).abs() < 1e-4);
+                    assert!(playable_offsets.contains(nearest.chunk_index, nearest.chunk_offset));
+                }
+            }
+        }
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn features_speed_up_over_reference() {
+        const RUNS: u32 = 20;
+
+        let motion_asset = synthetic_motion_asset(200, 600);
+        let features = TrajectoryFeatures::build(&motion_asset, &TRAJECTORY_CONFIG);
+        let playable_offsets = PlayableOffsets::new(&motion_asset, 1.0);
+        let traj = query_trajectory(&motion_asset, 3, 100);
+        let traj_offsets = offsets(&traj);
+
+        let start_time = Instant::now();
+        for _ in 0..RUNS {
+            features.nearest(&traj_offsets, 5, 0.3, &playable_offsets);
+        }
+        let features_duration = start_time.elapsed().as_secs_f64() * 1000.0 / RUNS as f64;
+
+        let start_time = Instant::now();
+        for _ in 0..RUNS {
+            reference_nearest(
+                &motion_asset,
+                &traj,
+                &TRAJECTORY_CONFIG,
+                5,
+                0.3,
+                &playable_offsets,
+            );
+        }
+        let reference_duration = start_time.elapsed().as_secs_f64() * 1000.0 / RUNS as f64;
+
+        println!(
+            "{} trajectories: features {:.3} ms, reference {:.3} ms ({:.1}x)",
+            features.entries.len(),
+            features_duration,
+            reference_duration,
+            reference_duration / features_duration
+        );
+    }
+}
@@ -1,138 +1,129 @@
+use std::hash::Hasher;
 use std::time::Instant;
 
 use bevy::prelude::*;
 use kdtree::distance::squared_euclidean;
 use kdtree::KdTree;
+use serde::{Deserialize, Serialize};
 
-use crate::motion::chunk::ChunkIterator;
+use crate::motion::motion_asset::MotionAsset;
+use crate::motion::motion_player::MotionPlayerConfig;
 use crate::motion::MotionData;
 use crate::trajectory::{Trajectory, TrajectoryConfig};
 use crate::ui::play_mode::MotionMatchingResult;
-use crate::{Method, BVH_SCALE_RATIO};
+use crate::{Method, MotionUpdate};
 
+use super::brute_force_match::BoundedHeap;
+use super::search_index::{
+    hash_motion_source, invalidate_search_index, save_search_index, SavedIndexState,
+    SavedSearchIndex, SearchIndex, SearchIndexPlugin,
+};
+use super::search_schedule::SearchSchedule;
 use super::{
-    MatchConfig, MatchTrajectory, MotionMatchingSet, NearestTrajectories, TrajectoryMatch,
-    PEAK_ALLOC,
+    iter_data_offsets, min_playable_duration, offset_distance, prediction_search_interval,
+    trajectory_offsets, MatchConfig, MatchTrajectory, MotionMatchingSet, NearestTrajectories,
+    PlayableOffsets, TrajectoryMatch, PEAK_ALLOC,
 };
 
 pub struct KdTreeMatchPlugin;
 
 impl Plugin for KdTreeMatchPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            PreUpdate,
-            populate_kdtree
-                .run_if(not(resource_exists::<KdTreeResource>))
-                .run_if(in_state(Method::KdTree)),
-        )
-        .add_systems(
-            Update,
-            trajectory_match_with_kdtree
-                .in_set(MotionMatchingSet::GlobalMatch)
-                .run_if(resource_exists::<KdTreeResource>)
-                .run_if(in_state(Method::KdTree)),
-        );
+        app.add_plugins(SearchIndexPlugin::<KdTreeIndex>::default())
+            .add_systems(
+                PreUpdate,
+                (
+                    invalidate_search_index::<KdTreeIndex, KdTreeResource>,
+                    populate_kdtree
+                        .run_if(not(resource_exists::<KdTreeResource>))
+                        .run_if(in_state(Method::KdTree)),
+                )
+                    .chain(),
+            )
+            .add_systems(
+                MotionUpdate,
+                trajectory_match_with_kdtree
+                    .in_set(MotionMatchingSet::GlobalMatch)
+                    .run_if(resource_exists::<KdTreeResource>)
+                    .run_if(in_state(Method::KdTree)),
+            );
     }
 }
 
+/// Load the saved [`KdTreeIndex`] if it is still fresh, otherwise rebuild and save it.
 fn populate_kdtree(
     mut commands: Commands,
     motion_data: MotionData,
+    saved_index: SavedSearchIndex<KdTreeIndex>,
     trajectory_config: Res<TrajectoryConfig>,
 ) {
-    let Some(motion_data) = motion_data.get() else {
+    let Some(motion_asset) = motion_data.get() else {
         return;
     };
 
-    let num_segments = trajectory_config.num_segments();
-    let num_points = trajectory_config.num_points();
-
-    let mut kdtree = KdTree::new(num_segments * 2);
-
-    // Populate KD-Tree with motion data
-    for (chunk_index, chunk) in motion_data.trajectory_data.iter_chunk().enumerate() {
-        let num_trajectories = chunk.len() - num_segments;
-
-        for chunk_offset in 0..num_trajectories {
-            let data_traj = &chunk[chunk_offset..chunk_offset + num_points];
-            let data_inv_matrix = data_traj[trajectory_config.history_count].matrix.inverse();
-
-            let data_traj = data_traj
-                .iter()
-                .map(|point| {
-                    let (.., translation) = point.matrix.to_scale_rotation_translation();
-                    data_inv_matrix.transform_point3(translation).xz()
-                })
-                .collect::<Vec<_>>();
-
-            let mut traj_offsets = Vec::new();
-            // Add each offset from the trajectory to the KD-Tree
-            for i in 1..data_traj.len() {
-                let offset = (data_traj[i] - data_traj[i - 1]) * BVH_SCALE_RATIO;
-                traj_offsets.push(offset.x);
-                traj_offsets.push(offset.y);
-            }
+    let source_hash =
+        KdTreeIndex::hash_source(motion_asset, &trajectory_config, &trajectory_config);
 
-            kdtree
-                .add(traj_offsets, (chunk_index, chunk_offset))
-                .unwrap();
+    let kdtree_index = match saved_index.get(source_hash) {
+        SavedIndexState::Loading => return,
+        SavedIndexState::Fresh(kdtree_index) => kdtree_index.clone(),
+        SavedIndexState::Stale => {
+            info!("KD-Tree index is stale, rebuilding...");
+            let kdtree_index = KdTreeIndex::build(motion_asset, &trajectory_config);
+            // The index is still usable for this session if it cannot be saved.
+            if let Err(err) = save_search_index(&kdtree_index) {
+                warn!("Could not save KD-Tree index: {err}");
+            }
+            kdtree_index
         }
-    }
-    commands.insert_resource(KdTreeResource(kdtree));
+    };
+
+    commands.insert_resource(KdTreeResource(kdtree_index));
 }
 
 fn trajectory_match_with_kdtree(
-    q_trajectory: Query<(&Trajectory, &Transform)>,
+    motion_data: MotionData,
+    q_trajectory: Query<(&Trajectory, &Transform, Option<&SearchSchedule>)>,
     mut match_evr: EventReader<TrajectoryMatch>,
     match_config: Res<MatchConfig>,
+    trajectory_config: Res<TrajectoryConfig>,
+    motion_player_config: Res<MotionPlayerConfig>,
     mut nearest_trajectories_evw: EventWriter<NearestTrajectories>,
     kd_tree: Res<KdTreeResource>,
     mut motion_matching_result: ResMut<MotionMatchingResult>,
 ) {
     // println!("KDTree Method");
     PEAK_ALLOC.reset_peak_usage();
+    let Some(motion_asset) = motion_data.get() else {
+        return;
+    };
+
+    let prediction_interval = prediction_search_interval(&trajectory_config, &motion_player_config);
     for traj_match in match_evr.read() {
         let entity = **traj_match;
-        let Ok((traj, transform)) = q_trajectory.get(entity) else {
+        let Ok((traj, transform, search_schedule)) = q_trajectory.get(entity) else {
             continue;
         };
 
-        let inv_matrix = transform.compute_matrix().inverse();
-        let traj = traj
-            .iter()
-            .map(|&(mut point)| {
-                point.translation = inv_matrix
-                    .transform_point3(Vec3::new(point.translation.x, 0.0, point.translation.y))
-                    .xz();
-                point.translation
-            })
-            .collect::<Vec<_>>();
-
-        let mut traj_offsets = Vec::new();
-        // Create trajectory offset.
-        for i in 1..traj.len() {
-            let offset = traj[i] - traj[i - 1];
-            traj_offsets.push(offset.x);
-            traj_offsets.push(offset.y);
-        }
+        // Candidates must keep playing until the next search of this entity.
+        let search_interval = search_schedule.map_or(prediction_interval, |search_schedule| {
+            search_schedule.search_interval(prediction_interval)
+        });
+        let playable_offsets = PlayableOffsets::new(
+            motion_asset,
+            min_playable_duration(search_interval, &motion_player_config),
+        );
+
+        let traj_offsets = trajectory_offsets(traj, transform);
 
         let start_time = Instant::now();
 
-        let nearest_trajs = kd_tree
-            .nearest(
-                &traj_offsets,
-                match_config.max_match_count,
-                &squared_euclidean,
-            )
-            .unwrap()
-            .into_iter()
-            .filter(|(distance, (..))| *distance < match_config.match_threshold)
-            .map(|(distance, &(chunk_index, chunk_offset))| MatchTrajectory {
-                distance,
-                chunk_index,
-                chunk_offset,
-            })
-            .collect::<Vec<_>>();
+        let nearest_trajs = kd_tree.nearest(
+            &traj_offsets,
+            match_config.max_match_count,
+            match_config.match_threshold,
+            &playable_offsets,
+        );
 
         let traj_duration = start_time.elapsed().as_secs_f64() * 1000.0;
 
@@ -159,25 +150,130 @@ fn trajectory_match_with_kdtree(
     }
 }
 
-pub fn offset_distance(offsets0: &[f32], offsets1: &[f32]) -> f32 {
-    let len = offsets0.len();
-    debug_assert_eq!(len, offsets1.len());
+/// KD-Tree of the trajectory offsets of a [`MotionAsset`].
+#[derive(Asset, TypePath, Serialize, Deserialize, Debug, Clone)]
+pub struct KdTreeIndex {
+    /// Hash of the source data this index was built from.
+    source_hash: u64,
+    /// KD-Tree of trajectory offsets pointing to [`Self::entries`].
+    kdtree: KdTree<f32, usize, Vec<f32>>,
+    entries: Vec<KdTreeEntry>,
+}
+
+impl KdTreeIndex {
+    /// Build a KD-Tree from all trajectories inside the [`MotionAsset`].
+    pub fn build(motion_asset: &MotionAsset, trajectory_config: &TrajectoryConfig) -> Self {
+        let mut kdtree = KdTree::new(trajectory_config.num_segments() * 2);
+        let mut entries = Vec::new();
+
+        // Populate KD-Tree with motion data
+        for (chunk_index, chunk_offset, offsets) in
+            iter_data_offsets(motion_asset, trajectory_config)
+        {
+            kdtree.add(offsets.clone(), entries.len()).unwrap();
+            entries.push(KdTreeEntry {
+                chunk_index,
+                chunk_offset,
+                offsets,
+            });
+        }
+
+        Self {
+            source_hash: Self::hash_source(motion_asset, trajectory_config, trajectory_config),
+            kdtree,
+            entries,
+        }
+    }
+
+    /// Search for the nearest playable trajectories (sorted by distance).
+    ///
+    /// The KD-Tree is traversed in euclidean order while candidates are re-scored using
+    /// [`offset_distance`], so that the result is identical to a brute force search.
+    /// Traversal stops once no remaining candidate can beat the current matches.
+    pub fn nearest(
+        &self,
+        traj_offsets: &[f32],
+        max_match_count: usize,
+        match_threshold: f32,
+        playable_offsets: &PlayableOffsets,
+    ) -> Vec<MatchTrajectory> {
+        if max_match_count == 0 {
+            return Vec::new();
+        }
+
+        let Ok(candidates) = self.kdtree.iter_nearest(traj_offsets, &squared_euclidean) else {
+            return Vec::new();
+        };
+
+        let mut nearest_trajs = BoundedHeap::new(max_match_count);
 
-    let mut offset_distance = 0.0;
+        let num_segments = (traj_offsets.len() / 2) as f32;
 
-    for i in 0..len / 2 {
-        let x_index = i * 2;
-        let y_index = x_index + 1;
+        for (squared_distance, &entry_index) in candidates {
+            // The sum of segment distances is never below the euclidean distance,
+            // which gives us a lower bound for this and all remaining candidates.
+            let min_distance = f32::sqrt(squared_distance) / num_segments;
+
+            if min_distance > match_threshold {
+                break;
+            }
+            if nearest_trajs
+                .worst_distance()
+                .is_some_and(|worst_distance| min_distance >= worst_distance)
+            {
+                break;
+            }
+
+            let entry = &self.entries[entry_index];
+            if !playable_offsets.contains(entry.chunk_index, entry.chunk_offset) {
+                continue;
+            }
 
-        let offset0 = Vec2::new(offsets0[x_index], offsets0[y_index]);
-        let offset1 = Vec2::new(offsets1[x_index], offsets1[y_index]);
+            let distance = offset_distance(traj_offsets, &entry.offsets);
 
-        offset_distance += offset0.distance(offset1);
+            // Distance must be below the threshold.
+            if distance > match_threshold {
+                continue;
+            }
+
+            nearest_trajs.push(MatchTrajectory {
+                distance,
+                chunk_index: entry.chunk_index,
+                chunk_offset: entry.chunk_offset,
+            });
+        }
+
+        nearest_trajs.into_sorted_vec()
     }
+}
+
+impl SearchIndex for KdTreeIndex {
+    const EXTENSION: &'static str = "kdtree.json";
 
-    offset_distance /= (len / 2).saturating_sub(1) as f32;
-    offset_distance
+    type Config = TrajectoryConfig;
+
+    fn source_hash(&self) -> u64 {
+        self.source_hash
+    }
+
+    fn hash_source(
+        motion_asset: &MotionAsset,
+        trajectory_config: &TrajectoryConfig,
+        _config: &TrajectoryConfig,
+    ) -> u64 {
+        hash_motion_source(motion_asset, trajectory_config).finish()
+    }
 }
 
-#[derive(Resource, Deref, DerefMut)]
-pub struct KdTreeResource(KdTree<f32, (usize, usize), Vec<f32>>);
+/// The [`KdTreeIndex`] in use.
+#[derive(Resource, Debug, Deref, DerefMut)]
+pub struct KdTreeResource(pub KdTreeIndex);
+
+/// A trajectory inside the [`KdTreeIndex`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct KdTreeEntry {
+    chunk_index: usize,
+    chunk_offset: usize,
+    /// Offsets between each trajectory point.
+    offsets: Vec<f32>,
+}
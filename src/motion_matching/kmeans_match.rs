@@ -1,16 +1,28 @@
+use std::hash::Hasher;
 use std::time::Instant;
 
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    motion::{chunk::ChunkIterator, MotionData},
+    motion::{motion_asset::MotionAsset, motion_player::MotionPlayerConfig, MotionData},
     motion_matching::MatchTrajectory,
     trajectory::{Trajectory, TrajectoryConfig},
     ui::play_mode::MotionMatchingResult,
-    Method, BVH_SCALE_RATIO,
+    Method, MotionUpdate,
 };
 
-use super::{MatchConfig, MotionMatchingSet, NearestTrajectories, TrajectoryMatch, PEAK_ALLOC};
+use super::brute_force_match::BoundedHeap;
+use super::search_index::{
+    hash_motion_source, invalidate_search_index, save_search_index, SavedIndexState,
+    SavedSearchIndex, SearchIndex, SearchIndexPlugin,
+};
+use super::search_schedule::SearchSchedule;
+use super::{
+    iter_data_offsets, min_playable_duration, offset_distance, prediction_search_interval,
+    trajectory_offsets, MatchConfig, MotionMatchingSet, NearestTrajectories, PlayableOffsets,
+    TrajectoryMatch, PEAK_ALLOC,
+};
 
 use clustering::*;
 
@@ -18,14 +30,24 @@ pub struct KMeansMatchPlugin;
 
 impl Plugin for KMeansMatchPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
+        app.insert_resource(KMeansConfig {
+            k: 10,
+            max_iter: 70,
+            probe_count: 3,
+        })
+        .add_plugins(SearchIndexPlugin::<KMeansIndex>::default())
+        .add_systems(
             PreUpdate,
-            populate_kmeans
-                .run_if(not(resource_exists::<KMeansResource>))
-                .run_if(in_state(Method::KMeans)),
+            (
+                invalidate_search_index::<KMeansIndex, KMeansResource>,
+                populate_kmeans
+                    .run_if(not(resource_exists::<KMeansResource>))
+                    .run_if(in_state(Method::KMeans)),
+            )
+                .chain(),
         )
         .add_systems(
-            Update,
+            MotionUpdate,
             trajectory_match_with_kmeans
                 .in_set(MotionMatchingSet::GlobalMatch)
                 .run_if(resource_exists::<KMeansResource>)
@@ -33,150 +55,87 @@ impl Plugin for KMeansMatchPlugin {
         );
     }
 }
+
+/// Load the saved [`KMeansIndex`] if it was built from the current motion data and
+/// configurations, otherwise cluster the motion data from scratch and save it.
 fn populate_kmeans(
     mut commands: Commands,
     motion_data: MotionData,
+    saved_index: SavedSearchIndex<KMeansIndex>,
     trajectory_config: Res<TrajectoryConfig>,
+    kmeans_config: Res<KMeansConfig>,
 ) {
-    let Some(motion_data) = motion_data.get() else {
+    let Some(motion_asset) = motion_data.get() else {
         return;
     };
 
-    let num_segments = trajectory_config.num_segments();
-    let num_points = trajectory_config.num_points();
-
-    let mut trajectory_offsets = Vec::new();
-
-    for (chunk_index, chunk) in motion_data.trajectory_data.iter_chunk().enumerate() {
-        let num_trajectories = chunk.len() - num_segments;
-
-        for chunk_offset in 0..num_trajectories {
-            let data_traj = &chunk[chunk_offset..chunk_offset + num_points];
-            let data_inv_matrix = data_traj[trajectory_config.history_count].matrix.inverse();
-
-            let data_traj = data_traj
-                .iter()
-                .map(|point| {
-                    let (.., translation) = point.matrix.to_scale_rotation_translation();
-                    data_inv_matrix.transform_point3(translation).xz()
-                })
-                .collect::<Vec<_>>();
-
-            let mut traj_offsets = Vec::new();
-            for i in 1..data_traj.len() {
-                let offset = (data_traj[i] - data_traj[i - 1]) * BVH_SCALE_RATIO;
-                traj_offsets.push(offset.x);
-                traj_offsets.push(offset.y);
+    let source_hash = KMeansIndex::hash_source(motion_asset, &trajectory_config, &kmeans_config);
+
+    let kmeans_index = match saved_index.get(source_hash) {
+        SavedIndexState::Loading => return,
+        SavedIndexState::Fresh(kmeans_index) => kmeans_index.clone(),
+        SavedIndexState::Stale => {
+            info!(
+                "Clustering motion data (k: {}, max_iter: {})...",
+                kmeans_config.k, kmeans_config.max_iter
+            );
+            let kmeans_index = KMeansIndex::build(motion_asset, &trajectory_config, &kmeans_config);
+            // The index is still usable for this session if it cannot be saved.
+            if let Err(err) = save_search_index(&kmeans_index) {
+                warn!("Could not save K-Means index: {err}");
             }
-
-            trajectory_offsets.push((traj_offsets, chunk_index, chunk_offset));
+            kmeans_index
         }
-    }
-
-    let data: Vec<Vec<f64>> = trajectory_offsets
-        .iter()
-        .map(|(offsets, _, _)| offsets.iter().map(|&x| x as f64).collect())
-        .collect();
-
-    // Number of clusters, 8 random centroid will be chosen
-    let k = 10;
-    // Max iterations
-    let max_iter = 70;
-    let clustering = kmeans(k, &data, max_iter);
-
-    let mut cluster_members: Vec<Vec<(usize, usize, Vec<f32>)>> = vec![Vec::new(); k];
-
-    for (i, cluster_id) in clustering.membership.iter().enumerate() {
-        let (offsets, chunk_index, chunk_offset) = &trajectory_offsets[i];
-        cluster_members[*cluster_id].push((*chunk_index, *chunk_offset, offsets.clone()));
-    }
+    };
 
-    commands.insert_resource(KMeansResource {
-        centroids: clustering.centroids,
-        cluster_memberships: clustering.membership,
-        trajectory_offsets,
-        cluster_members,
-    })
+    commands.insert_resource(KMeansResource(kmeans_index));
 }
 
 fn trajectory_match_with_kmeans(
-    q_trajectory: Query<(&Trajectory, &Transform)>,
+    motion_data: MotionData,
+    q_trajectory: Query<(&Trajectory, &Transform, Option<&SearchSchedule>)>,
     mut match_evr: EventReader<TrajectoryMatch>,
     match_config: Res<MatchConfig>,
+    trajectory_config: Res<TrajectoryConfig>,
+    motion_player_config: Res<MotionPlayerConfig>,
+    kmeans_config: Res<KMeansConfig>,
     mut nearest_trajectories_evw: EventWriter<NearestTrajectories>,
     kmeans: Res<KMeansResource>,
     mut motion_matching_result: ResMut<MotionMatchingResult>,
 ) {
     // println!("KMeans Method");
     PEAK_ALLOC.reset_peak_usage();
+    let Some(motion_asset) = motion_data.get() else {
+        return;
+    };
+
+    let prediction_interval = prediction_search_interval(&trajectory_config, &motion_player_config);
     for traj_match in match_evr.read() {
         let entity = **traj_match;
-        let Ok((traj, transform)) = q_trajectory.get(entity) else {
+        let Ok((traj, transform, search_schedule)) = q_trajectory.get(entity) else {
             continue;
         };
 
-        let inv_matrix = transform.compute_matrix().inverse();
-        let traj = traj
-            .iter()
-            .map(|&(mut point)| {
-                point.translation = inv_matrix
-                    .transform_point3(Vec3::new(point.translation.x, 0.0, point.translation.y))
-                    .xz();
-                point.translation
-            })
-            .collect::<Vec<_>>();
+        // Candidates must keep playing until the next search of this entity.
+        let search_interval = search_schedule.map_or(prediction_interval, |search_schedule| {
+            search_schedule.search_interval(prediction_interval)
+        });
+        let playable_offsets = PlayableOffsets::new(
+            motion_asset,
+            min_playable_duration(search_interval, &motion_player_config),
+        );
 
-        let mut traj_offsets = Vec::new();
-
-        for i in 1..traj.len() {
-            let offset = traj[i] - traj[i - 1];
-            traj_offsets.push(offset.x);
-            traj_offsets.push(offset.y);
-        }
+        let traj_offsets = trajectory_offsets(traj, transform);
 
         let start_time = Instant::now();
 
-        let mut nearest_centroids = Vec::new();
-        for (i, centroid) in kmeans.centroids.iter().enumerate() {
-            let centroid_f32: Vec<f32> = centroid.0.iter().map(|&x| x as f32).collect();
-            let distance = offset_distance(&traj_offsets, &centroid_f32);
-
-            if distance <= match_config.match_threshold {
-                nearest_centroids.push((distance, i));
-            } else {
-                continue;
-            }
-        }
-
-        let mut nearest_trajs = Vec::with_capacity(match_config.max_match_count);
-        for (_distance, centroid_index) in nearest_centroids {
-            if let Some(members) = kmeans.cluster_members.get(centroid_index) {
-                for (chunk_index, chunk_offset, offsets) in members {
-                    let distance = offset_distance(&traj_offsets, offsets);
-
-                    if distance > match_config.match_threshold {
-                        continue;
-                    }
-
-                    if nearest_trajs.len() < match_config.max_match_count {
-                        // Stack not yet full, push into it
-                        nearest_trajs.push(MatchTrajectory {
-                            distance,
-                            chunk_index: *chunk_index,
-                            chunk_offset: *chunk_offset,
-                        });
-                    } else if let Some(worst_match) = nearest_trajs.last_mut() {
-                        if distance < worst_match.distance {
-                            *worst_match = MatchTrajectory {
-                                distance,
-                                chunk_index: *chunk_index,
-                                chunk_offset: *chunk_offset,
-                            };
-                        }
-                    }
-                }
-            }
-        }
+        let nearest_trajs = kmeans.nearest(
+            &traj_offsets,
+            kmeans_config.probe_count,
+            match_config.max_match_count,
+            match_config.match_threshold,
+            &playable_offsets,
+        );
 
         let traj_duration = start_time.elapsed().as_secs_f64() * 1000.0;
 
@@ -196,7 +155,6 @@ fn trajectory_match_with_kmeans(
                 / runs as f64;
 
         motion_matching_result.matching_result.runs = runs;
-        nearest_trajs.sort_by(|t0, t1| t0.distance.total_cmp(&t1.distance));
 
         nearest_trajectories_evw.send(NearestTrajectories {
             trajectories: nearest_trajs,
@@ -205,31 +163,171 @@ fn trajectory_match_with_kmeans(
     }
 }
 
-pub fn offset_distance(offsets0: &[f32], offsets1: &[f32]) -> f32 {
-    let len = offsets0.len();
-    debug_assert_eq!(len, offsets1.len());
+/// Clustered trajectory offsets of a [`MotionAsset`].
+///
+/// Saved next to the motion asset so that it does not need to be recomputed on every launch.
+#[derive(Asset, TypePath, Serialize, Deserialize, Debug, Clone)]
+pub struct KMeansIndex {
+    /// Hash of the source data and configurations this index was built from.
+    source_hash: u64,
+    /// Number of clusters used to build this index.
+    k: usize,
+    /// Maximum iterations used to build this index.
+    max_iter: usize,
+    /// Trajectory offsets of each cluster's centroid.
+    pub centroids: Vec<Vec<f32>>,
+    /// Trajectories that belong to each cluster.
+    pub cluster_members: Vec<Vec<KMeansMember>>,
+}
+
+impl KMeansIndex {
+    /// Cluster all trajectories inside the [`MotionAsset`].
+    pub fn build(
+        motion_asset: &MotionAsset,
+        trajectory_config: &TrajectoryConfig,
+        kmeans_config: &KMeansConfig,
+    ) -> Self {
+        let members = iter_data_offsets(motion_asset, trajectory_config)
+            .map(|(chunk_index, chunk_offset, offsets)| KMeansMember {
+                chunk_index,
+                chunk_offset,
+                offsets,
+            })
+            .collect::<Vec<_>>();
+
+        let data: Vec<Vec<f64>> = members
+            .iter()
+            .map(|member| member.offsets.iter().map(|&x| x as f64).collect())
+            .collect();
+
+        let clustering = kmeans(kmeans_config.k, &data, kmeans_config.max_iter);
+
+        let centroids = clustering
+            .centroids
+            .iter()
+            .map(|centroid| centroid.0.iter().map(|&x| x as f32).collect())
+            .collect::<Vec<_>>();
+
+        let mut cluster_members = vec![Vec::new(); centroids.len()];
+        for (member, &cluster_id) in members.into_iter().zip(clustering.membership.iter()) {
+            cluster_members[cluster_id].push(member);
+        }
+
+        Self {
+            source_hash: Self::hash_source(motion_asset, trajectory_config, kmeans_config),
+            k: kmeans_config.k,
+            max_iter: kmeans_config.max_iter,
+            centroids,
+            cluster_members,
+        }
+    }
+
+    /// Search for the nearest playable trajectories (sorted by distance).
+    ///
+    /// Clusters are probed from the nearest centroid outwards. At least `probe_count`
+    /// clusters are always probed, any further cluster is only probed if its centroid
+    /// is within `match_threshold`.
+    pub fn nearest(
+        &self,
+        traj_offsets: &[f32],
+        probe_count: usize,
+        max_match_count: usize,
+        match_threshold: f32,
+        playable_offsets: &PlayableOffsets,
+    ) -> Vec<MatchTrajectory> {
+        let mut nearest_centroids = self
+            .centroids
+            .iter()
+            .enumerate()
+            .map(|(i, centroid)| (offset_distance(traj_offsets, centroid), i))
+            .collect::<Vec<_>>();
+        nearest_centroids.sort_by(|c0, c1| c0.0.total_cmp(&c1.0));
+
+        let mut nearest_trajs = BoundedHeap::new(max_match_count);
+        for (probe, (centroid_distance, centroid_index)) in
+            nearest_centroids.into_iter().enumerate()
+        {
+            // Centroids are sorted, so the remaining ones are all beyond the threshold.
+            if probe >= probe_count && centroid_distance > match_threshold {
+                break;
+            }
 
-    let mut offset_distance = 0.0;
+            for member in self.cluster_members[centroid_index].iter() {
+                if !playable_offsets.contains(member.chunk_index, member.chunk_offset) {
+                    continue;
+                }
+
+                let distance = offset_distance(traj_offsets, &member.offsets);
+
+                if distance > match_threshold {
+                    continue;
+                }
+
+                nearest_trajs.push(MatchTrajectory {
+                    distance,
+                    chunk_index: member.chunk_index,
+                    chunk_offset: member.chunk_offset,
+                });
+            }
+        }
+
+        nearest_trajs.into_sorted_vec()
+    }
+}
 
-    for i in 0..len / 2 {
-        let x_index = i * 2;
-        let y_index = x_index + 1;
+impl SearchIndex for KMeansIndex {
+    const EXTENSION: &'static str = "kmeans.json";
 
-        let offset0 = Vec2::new(offsets0[x_index], offsets0[y_index]);
-        let offset1 = Vec2::new(offsets1[x_index], offsets1[y_index]);
+    type Config = KMeansConfig;
 
-        offset_distance += offset0.distance(offset1);
+    fn source_hash(&self) -> u64 {
+        self.source_hash
     }
 
-    offset_distance /= (len / 2).saturating_sub(1) as f32;
-    offset_distance
+    fn hash_source(
+        motion_asset: &MotionAsset,
+        trajectory_config: &TrajectoryConfig,
+        kmeans_config: &KMeansConfig,
+    ) -> u64 {
+        let mut hasher = hash_motion_source(motion_asset, trajectory_config);
+        hasher.write_u64(kmeans_config.k as u64);
+        hasher.write_u64(kmeans_config.max_iter as u64);
+        hasher.finish()
+    }
 }
 
-#[derive(Resource)]
-pub struct KMeansResource {
-    pub centroids: Vec<Centroid>,
-    pub cluster_memberships: Vec<usize>,
-    // trajectory offsets with chunk index and chunk offset
-    pub trajectory_offsets: Vec<(Vec<f32>, usize, usize)>,
-    pub cluster_members: Vec<Vec<(usize, usize, Vec<f32>)>>,
+// Getters
+impl KMeansIndex {
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    pub fn max_iter(&self) -> usize {
+        self.max_iter
+    }
 }
+
+/// A trajectory inside a [`KMeansIndex`] cluster.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KMeansMember {
+    pub chunk_index: usize,
+    pub chunk_offset: usize,
+    /// Offsets between each trajectory point.
+    pub offsets: Vec<f32>,
+}
+
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct KMeansConfig {
+    /// Number of clusters.
+    pub k: usize,
+    /// Maximum iterations for clustering.
+    pub max_iter: usize,
+    /// Minimum number of nearest clusters to probe during search.
+    pub probe_count: usize,
+}
+
+/// The [`KMeansIndex`] in use.
+///
+/// Remove this resource to rebuild the index using the latest [`KMeansConfig`].
+#[derive(Resource, Debug, Deref, DerefMut)]
+pub struct KMeansResource(pub KMeansIndex);
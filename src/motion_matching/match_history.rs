@@ -0,0 +1,158 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::motion::motion_player::{MotionPlayer, MotionPose};
+
+use super::MatchConfig;
+
+pub struct MatchHistoryPlugin;
+
+impl Plugin for MatchHistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PreUpdate, init_match_history);
+    }
+}
+
+fn init_match_history(mut commands: Commands, q_players: Query<Entity, Added<MotionPlayer>>) {
+    for entity in q_players.iter() {
+        commands.entity(entity).insert(MatchHistory::default());
+    }
+}
+
+/// Recently played segments of a [`MotionPlayer`].
+///
+/// Used to penalize re-selecting frames that were just played,
+/// which prevents oscillating between 2 animations.
+#[derive(Component, Default, Debug)]
+pub struct MatchHistory {
+    /// Segments that have been played and switched away from (oldest first).
+    segments: VecDeque<PlayedSegment>,
+    /// Where the currently playing segment started.
+    current_start: Option<MotionPose>,
+}
+
+impl MatchHistory {
+    /// Record the selected [`MotionPose`] to play next.
+    ///
+    /// `current_pose` is the pose that is playing right now, which closes the current segment
+    /// unless `next_pose` is a continuation of it.
+    pub fn record_selection(
+        &mut self,
+        current_pose: Option<&MotionPose>,
+        next_pose: &MotionPose,
+        is_continuation: bool,
+        elapsed_secs: f32,
+        match_config: &MatchConfig,
+    ) {
+        if is_continuation && self.current_start.is_some() {
+            return;
+        }
+
+        if let (Some(start), Some(current_pose)) = (self.current_start, current_pose) {
+            if start.chunk_index == current_pose.chunk_index {
+                self.segments.push_back(PlayedSegment {
+                    chunk_index: start.chunk_index,
+                    start_time: start.time,
+                    end_time: current_pose.time,
+                    expire_time: elapsed_secs + match_config.cooldown_duration,
+                });
+            }
+        }
+
+        self.current_start = Some(*next_pose);
+    }
+
+    /// Remove segments that are past their cooldown.
+    pub fn prune(&mut self, elapsed_secs: f32) {
+        while self
+            .segments
+            .front()
+            .is_some_and(|segment| segment.expire_time <= elapsed_secs)
+        {
+            self.segments.pop_front();
+        }
+    }
+
+    /// Penalty for selecting the frame at `time` inside `chunk_index`.
+    pub fn penalty(&self, chunk_index: usize, time: f32, match_config: &MatchConfig) -> f32 {
+        match self
+            .segments
+            .iter()
+            .any(|segment| segment.contains(chunk_index, time, match_config.cooldown_window))
+        {
+            true => match_config.cooldown_penalty,
+            false => 0.0,
+        }
+    }
+}
+
+/// A range of time that has been played inside a chunk.
+#[derive(Debug, Clone, Copy)]
+pub struct PlayedSegment {
+    pub chunk_index: usize,
+    pub start_time: f32,
+    /// Smaller than [`Self::start_time`] if the animation has looped.
+    pub end_time: f32,
+    /// Elapsed time in seconds when this segment leaves the history.
+    pub expire_time: f32,
+}
+
+impl PlayedSegment {
+    /// Is `time` inside `chunk_index` within `window` seconds of this segment?
+    pub fn contains(&self, chunk_index: usize, time: f32, window: f32) -> bool {
+        if chunk_index != self.chunk_index {
+            return false;
+        }
+
+        let after_start = time >= self.start_time - window;
+        let before_end = time <= self.end_time + window;
+
+        match self.start_time <= self.end_time {
+            true => after_start && before_end,
+            // Looped over the end of the animation.
+            false => after_start || before_end,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start_time: f32, end_time: f32) -> PlayedSegment {
+        PlayedSegment {
+            chunk_index: 1,
+            start_time,
+            end_time,
+            expire_time: 0.0,
+        }
+    }
+
+    #[test]
+    fn played_segment_contains_its_range_and_window() {
+        let segment = segment(1.0, 2.0);
+
+        assert!(segment.contains(1, 1.5, 0.0));
+        assert!(segment.contains(1, 0.8, 0.25));
+        assert!(segment.contains(1, 2.2, 0.25));
+        assert!(!segment.contains(1, 0.7, 0.25));
+        assert!(!segment.contains(1, 2.3, 0.25));
+        assert!(!segment.contains(0, 1.5, 0.25));
+    }
+
+    #[test]
+    fn played_segment_contains_wrap_around() {
+        // Started near the end of a looping animation and continued from its beginning.
+        let segment = segment(2.5, 0.5);
+
+        assert!(segment.contains(1, 2.8, 0.0));
+        assert!(segment.contains(1, 0.2, 0.0));
+        assert!(segment.contains(1, 2.3, 0.25));
+        assert!(segment.contains(1, 0.7, 0.25));
+        assert!(!segment.contains(1, 1.5, 0.25));
+        assert!(!segment.contains(1, 2.2, 0.25));
+        assert!(!segment.contains(1, 0.8, 0.25));
+        assert!(!segment.contains(2, 2.8, 0.25));
+    }
+}
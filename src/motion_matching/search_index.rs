@@ -0,0 +1,248 @@
+//! Search indexes that are built from the [`MotionAsset`] and saved alongside it.
+
+use std::hash::Hasher;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext, LoadState};
+use bevy::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::motion::chunk::ChunkIterator;
+use crate::motion::motion_asset::{MotionAsset, MotionDataLoaderError};
+use crate::motion::MotionData;
+use crate::trajectory::TrajectoryConfig;
+
+/// Asset path of the motion data, search indexes are saved next to it.
+pub const MOTION_DATA_PATH: &str = "motion_data/motion_data";
+
+/// Loads the saved [`SearchIndex`] `T` into [`SearchIndexHandle<T>`].
+pub struct SearchIndexPlugin<T: SearchIndex>(PhantomData<T>);
+
+impl<T: SearchIndex> Default for SearchIndexPlugin<T> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T: SearchIndex> Plugin for SearchIndexPlugin<T> {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<T>()
+            .register_asset_loader(SearchIndexLoader::<T>(PhantomData))
+            .add_systems(PreStartup, load_search_index::<T>);
+    }
+}
+
+fn load_search_index<T: SearchIndex>(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let file_path = format!("{MOTION_DATA_PATH}.{}", T::EXTENSION);
+    let handle = asset_server.load::<T>(file_path);
+
+    commands.insert_resource(SearchIndexHandle(handle));
+}
+
+/// A search index built from a [`MotionAsset`].
+pub trait SearchIndex: Asset + Serialize + DeserializeOwned + Clone {
+    /// File extension of the saved index.
+    const EXTENSION: &'static str;
+
+    /// Configuration hashed into the source besides the [`TrajectoryConfig`].
+    ///
+    /// Use [`TrajectoryConfig`] itself when the index has no configuration of its own.
+    type Config: Resource;
+
+    /// Hash of the source data this index was built from.
+    fn source_hash(&self) -> u64;
+
+    /// Hash of the source data an index built right now would have.
+    fn hash_source(
+        motion_asset: &MotionAsset,
+        trajectory_config: &TrajectoryConfig,
+        config: &Self::Config,
+    ) -> u64;
+}
+
+/// Save the [`SearchIndex`] next to the motion data.
+pub fn save_search_index<T: SearchIndex>(index: &T) -> Result<(), SearchIndexError> {
+    let convert_to_json = serde_json::to_string(index)?;
+
+    let mut index_file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(format!("assets/{MOTION_DATA_PATH}.{}", T::EXTENSION))?;
+
+    index_file.write_all(convert_to_json.as_bytes())?;
+    Ok(())
+}
+
+/// Remove the search index resource `R` whenever the motion data or the saved index `T`
+/// is modified, or when a configuration change alters its source hash, so that it can be
+/// populated again.
+///
+/// Reloads of the saved index that hold the index already in use are ignored,
+/// e.g. the one caused by saving a freshly built index.
+pub fn invalidate_search_index<T: SearchIndex, R: Resource + Deref<Target = T>>(
+    mut commands: Commands,
+    index_resource: Option<Res<R>>,
+    index_assets: Res<Assets<T>>,
+    motion_data: MotionData,
+    trajectory_config: Res<TrajectoryConfig>,
+    config: Res<T::Config>,
+    mut motion_asset_evr: EventReader<AssetEvent<MotionAsset>>,
+    mut index_evr: EventReader<AssetEvent<T>>,
+) {
+    let mut modified = false;
+
+    for event in motion_asset_evr.read() {
+        modified |= matches!(event, AssetEvent::Modified { .. });
+    }
+    for event in index_evr.read() {
+        let AssetEvent::Modified { id } = event else {
+            continue;
+        };
+
+        modified |= match (&index_resource, index_assets.get(*id)) {
+            (Some(index_resource), Some(index)) => {
+                index.source_hash() != index_resource.source_hash()
+            }
+            _ => true,
+        };
+    }
+
+    if trajectory_config.is_changed() || config.is_changed() {
+        if let (Some(index_resource), Some(motion_asset)) = (&index_resource, motion_data.get()) {
+            modified |= T::hash_source(motion_asset, &trajectory_config, &config)
+                != index_resource.source_hash();
+        }
+    }
+
+    if modified {
+        commands.remove_resource::<R>();
+    }
+}
+
+#[derive(Resource, Debug, Deref, DerefMut)]
+pub struct SearchIndexHandle<T: SearchIndex>(pub Handle<T>);
+
+#[derive(bevy::ecs::system::SystemParam)]
+pub struct SavedSearchIndex<'w, T: SearchIndex> {
+    pub assets: Res<'w, Assets<T>>,
+    pub handle: Res<'w, SearchIndexHandle<T>>,
+    pub asset_server: Res<'w, AssetServer>,
+}
+
+impl<T: SearchIndex> SavedSearchIndex<'_, T> {
+    /// Get the saved index if it was built from the given source hash.
+    pub fn get(&self, source_hash: u64) -> SavedIndexState<&T> {
+        if let Some(index) = self.assets.get(&**self.handle) {
+            return match index.source_hash() == source_hash {
+                true => SavedIndexState::Fresh(index),
+                false => SavedIndexState::Stale,
+            };
+        }
+
+        match self.asset_server.load_state(&**self.handle) {
+            // There is no usable saved index.
+            LoadState::Failed(_) => SavedIndexState::Stale,
+            _ => SavedIndexState::Loading,
+        }
+    }
+}
+
+pub enum SavedIndexState<T> {
+    /// The saved index is still loading.
+    Loading,
+    /// The saved index was built from the same source.
+    Fresh(T),
+    /// The saved index is missing or was built from a different source.
+    Stale,
+}
+
+/// Hash of the source data that all search indexes are built from.
+///
+/// Search indexes can further hash their own configurations into it.
+pub fn hash_motion_source(
+    motion_asset: &MotionAsset,
+    trajectory_config: &TrajectoryConfig,
+) -> SourceHasher {
+    let mut hasher = SourceHasher::default();
+
+    hasher.write_u64(trajectory_config.num_points() as u64);
+    hasher.write_u64(trajectory_config.history_count as u64);
+
+    for chunk in motion_asset.trajectory_data.iter_chunk() {
+        hasher.write_u64(chunk.len() as u64);
+
+        for point in chunk {
+            for x in point.matrix.to_cols_array() {
+                hasher.write_u32(x.to_bits());
+            }
+            hasher.write_u32(point.velocity.x.to_bits());
+            hasher.write_u32(point.velocity.y.to_bits());
+        }
+    }
+
+    hasher
+}
+
+/// FNV-1a hasher, which stays stable across platforms and compiler versions.
+#[derive(Debug, Clone, Copy)]
+pub struct SourceHasher(u64);
+
+impl Default for SourceHasher {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for SourceHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+struct SearchIndexLoader<T: SearchIndex>(PhantomData<T>);
+
+impl<T: SearchIndex> AssetLoader for SearchIndexLoader<T> {
+    type Asset = T;
+    type Settings = ();
+    type Error = MotionDataLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        let index = serde_json::from_slice::<T>(&bytes)?;
+
+        Ok(index)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        std::slice::from_ref(&T::EXTENSION)
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum SearchIndexError {
+    #[error("Could not write search index file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not serialize search index using serde: {0}")]
+    Serde(#[from] serde_json::Error),
+}
@@ -0,0 +1,102 @@
+use bevy::prelude::*;
+
+pub struct SearchSchedulePlugin;
+
+impl Plugin for SearchSchedulePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<SearchSchedule>()
+            .register_type::<SearchMode>();
+    }
+}
+
+/// Decides when a character searches for a new animation.
+///
+/// Characters without this component use [`SearchMode::Prediction`].
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct SearchSchedule {
+    pub mode: SearchMode,
+    /// Force a search when the movement direction has changed by more than this distance
+    /// since the last search (e.g. 2.0 for a full flip of the stick direction).
+    ///
+    /// [`None`] never forces a search.
+    pub force_search_threshold: Option<f32>,
+    /// Time since the last search.
+    timer: Timer,
+    /// Movement direction during the last search.
+    last_direction: Vec2,
+}
+
+impl SearchSchedule {
+    pub fn new(mode: SearchMode) -> Self {
+        let interval = match mode {
+            SearchMode::Prediction => 0.0,
+            SearchMode::FixedInterval(interval) => interval,
+        };
+
+        Self {
+            mode,
+            force_search_threshold: Some(1.2),
+            timer: Timer::from_seconds(interval, TimerMode::Once),
+            last_direction: Vec2::ZERO,
+        }
+    }
+
+    /// Tick the timer of [`SearchMode::FixedInterval`].
+    ///
+    /// Returns true if a search is due.
+    pub fn tick(&mut self, delta: std::time::Duration) -> bool {
+        let SearchMode::FixedInterval(interval) = self.mode else {
+            return false;
+        };
+
+        // The interval may have been changed from the inspector.
+        self.timer
+            .set_duration(std::time::Duration::from_secs_f32(interval));
+        self.timer.tick(delta).finished()
+    }
+
+    /// Longest time (in seconds) until the next search, given the interval after which
+    /// [`SearchMode::Prediction`] searches.
+    ///
+    /// A prediction match may keep the playing animation, so [`SearchMode::FixedInterval`]
+    /// can take as long as its own interval.
+    pub fn search_interval(&self, prediction_interval: f32) -> f32 {
+        match self.mode {
+            SearchMode::Prediction => prediction_interval,
+            SearchMode::FixedInterval(interval) => f32::max(prediction_interval, interval),
+        }
+    }
+
+    /// Has the movement direction changed enough to force a search?
+    pub fn should_force_search(&self, direction: Vec2) -> bool {
+        self.force_search_threshold
+            .is_some_and(|threshold| Vec2::distance(self.last_direction, direction) > threshold)
+    }
+
+    /// Restart the schedule after a search.
+    pub fn reset(&mut self, direction: Vec2) {
+        self.timer.reset();
+        self.last_direction = direction;
+    }
+}
+
+impl Default for SearchSchedule {
+    fn default() -> Self {
+        Self::new(SearchMode::Prediction)
+    }
+}
+
+#[derive(Reflect, Debug, Clone, Copy, PartialEq)]
+pub enum SearchMode {
+    /// Search once the prediction of the playing animation
+    /// (minus the interpolation duration) has been played.
+    Prediction,
+    /// Search every given number of seconds.
+    ///
+    /// A search still happens once the prediction of the playing animation has been played,
+    /// so that the animation never runs out before the next search, and whenever
+    /// [`SearchSchedule::force_search_threshold`] is crossed. Set the threshold to [`None`]
+    /// to not search on direction changes.
+    FixedInterval(f32),
+}
@@ -0,0 +1,274 @@
+//! Drive entities along waypoints or a Catmull-Rom spline instead of player input.
+
+use bevy::prelude::*;
+
+use crate::draw_axes::{ColorPalette, DrawAxes};
+use crate::player::{MovementConfig, MovementInput, PlayerMarker, SpeedTier};
+use crate::trajectory::MovementDirection;
+use crate::transform2d::Transform2d;
+use crate::ui::play_mode::RunPresetDirection;
+use crate::{MainSet, MotionUpdate, LARGE_EPSILON};
+
+pub struct PathFollowPlugin;
+
+impl Plugin for PathFollowPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PreUpdate,
+            toggle_preset_path.run_if(
+                resource_changed::<RunPresetDirection>
+                    .and(not(resource_added::<RunPresetDirection>)),
+            ),
+        )
+        .add_systems(MotionUpdate, follow_paths.in_set(MainSet::Action))
+        .add_systems(Update, draw_paths);
+    }
+}
+
+/// Number of points sampled along each Catmull-Rom segment.
+const SPLINE_SUBDIVISIONS: usize = 8;
+
+/// Marks the [`PathFollower`] inserted by [`RunPresetDirection`].
+#[derive(Component, Debug, Clone, Copy)]
+struct PresetPath;
+
+/// Follow a looping square with the player, for testing.
+///
+/// Only the preset path is removed again, other [`PathFollower`]s are left untouched.
+fn toggle_preset_path(
+    mut commands: Commands,
+    q_players: Query<(&Transform2d, Has<PresetPath>, Entity), With<PlayerMarker>>,
+    run_preset_direction: Res<RunPresetDirection>,
+) {
+    for (transform2d, preset_path, entity) in q_players.iter() {
+        match **run_preset_direction {
+            true => {
+                let start = transform2d.translation;
+                let waypoints = [Vec2::ZERO, Vec2::Y, Vec2::ONE, Vec2::X]
+                    .map(|corner| start + corner * 4.0)
+                    .to_vec();
+
+                commands.entity(entity).insert((
+                    PathFollower::new(waypoints, PathInterpolation::Linear, true),
+                    PresetPath,
+                ));
+            }
+            false if preset_path => {
+                commands
+                    .entity(entity)
+                    .remove::<(PathFollower, PresetPath)>();
+            }
+            false => {}
+        }
+    }
+}
+
+fn follow_paths(
+    mut q_followers: Query<(
+        &mut PathFollower,
+        &mut MovementDirection,
+        &mut MovementInput,
+        &Transform2d,
+    )>,
+    movement_config: Res<MovementConfig>,
+    time: Res<Time>,
+) {
+    for (mut follower, mut movement_direction, mut movement_input, transform2d) in
+        q_followers.iter_mut()
+    {
+        let translation = transform2d.translation;
+        let target_direction = follower.advance(translation);
+
+        *movement_input = MovementInput {
+            moving: target_direction != Vec2::ZERO,
+            tier: follower.tier,
+        };
+
+        **movement_direction = Vec2::lerp(
+            **movement_direction,
+            target_direction,
+            f32::min(1.0, movement_config.lerp_factor * time.delta_secs()),
+        );
+    }
+}
+
+fn draw_paths(
+    q_followers: Query<&PathFollower>,
+    mut draw_axes: ResMut<DrawAxes>,
+    palette: Res<ColorPalette>,
+) {
+    for follower in q_followers.iter() {
+        for (i, point) in follower.points.iter().enumerate() {
+            let color = match i == follower.next_point {
+                true => palette.red,
+                false => palette.yellow,
+            };
+            draw_axes.draw_forward(
+                Mat4::from_translation(Vec3::new(point.x, 0.0, point.y)),
+                0.05,
+                color,
+            );
+        }
+    }
+}
+
+/// Moves an entity along a path by driving its [`MovementDirection`] and [`MovementInput`].
+///
+/// Entities with a [`PathFollower`] ignore player input.
+#[derive(Component, Debug, Clone)]
+pub struct PathFollower {
+    /// Speed tier to move along the path with.
+    pub tier: SpeedTier,
+    /// Distance to a point of the path at which it counts as reached.
+    pub reach_radius: f32,
+    /// Distance to the end of the path at which the entity starts to slow down.
+    ///
+    /// Not used for looping paths.
+    pub arrival_radius: f32,
+    /// Points of the path in world space, sampled from the spline if any.
+    points: Vec<Vec2>,
+    looping: bool,
+    /// Index of the point the entity is heading to.
+    next_point: usize,
+}
+
+impl PathFollower {
+    pub fn new(waypoints: Vec<Vec2>, interpolation: PathInterpolation, looping: bool) -> Self {
+        let points = match interpolation {
+            PathInterpolation::Linear => waypoints,
+            PathInterpolation::CatmullRom => catmull_rom_points(&waypoints, looping),
+        };
+
+        Self {
+            tier: SpeedTier::Walk,
+            reach_radius: 0.3,
+            arrival_radius: 1.0,
+            points,
+            looping,
+            next_point: 0,
+        }
+    }
+
+    /// Skip the points that have been reached and return the desired movement direction.
+    ///
+    /// The direction shrinks inside [`Self::arrival_radius`] of the end and becomes zero
+    /// once the end is reached.
+    fn advance(&mut self, translation: Vec2) -> Vec2 {
+        let len = self.points.len();
+        if len == 0 {
+            return Vec2::ZERO;
+        }
+
+        // Bounded, so that a path with all points inside the reach radius doesn't spin forever.
+        for _ in 0..len {
+            let is_last = !self.looping && self.next_point == len - 1;
+            if is_last || translation.distance(self.points[self.next_point]) > self.reach_radius {
+                break;
+            }
+            self.next_point = (self.next_point + 1) % len;
+        }
+
+        let offset = self.points[self.next_point] - translation;
+        let distance = offset.length();
+
+        if self.looping || self.next_point != len - 1 {
+            return offset.normalize_or_zero();
+        }
+
+        // Slow down towards the end of the path.
+        match distance > self.reach_radius * 0.5 {
+            true => {
+                offset / distance
+                    * f32::clamp(distance / self.arrival_radius.max(LARGE_EPSILON), 0.0, 1.0)
+            }
+            false => Vec2::ZERO,
+        }
+    }
+
+    /// Has the end of a non-looping path been reached?
+    pub fn arrived(&self, translation: Vec2) -> bool {
+        !self.looping
+            && self.points.last().is_none_or(|end| {
+                self.next_point + 1 >= self.points.len()
+                    && translation.distance(*end) <= self.reach_radius * 0.5
+            })
+    }
+
+    /// Start from the first point again.
+    pub fn restart(&mut self) {
+        self.next_point = 0;
+    }
+}
+
+// Getters
+impl PathFollower {
+    pub fn points(&self) -> &[Vec2] {
+        &self.points
+    }
+
+    pub fn looping(&self) -> bool {
+        self.looping
+    }
+
+    pub fn next_point(&self) -> usize {
+        self.next_point
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathInterpolation {
+    /// Straight lines between the waypoints.
+    #[default]
+    Linear,
+    /// A Catmull-Rom spline passing through the waypoints.
+    CatmullRom,
+}
+
+/// Sample a uniform Catmull-Rom spline through `waypoints`.
+///
+/// The end points of an open spline are duplicated so that it passes through all waypoints.
+fn catmull_rom_points(waypoints: &[Vec2], looping: bool) -> Vec<Vec2> {
+    let len = waypoints.len();
+    if len < 2 {
+        return waypoints.to_vec();
+    }
+
+    let get = |i: isize| match looping {
+        true => waypoints[i.rem_euclid(len as isize) as usize],
+        false => waypoints[i.clamp(0, len as isize - 1) as usize],
+    };
+
+    let segment_count = match looping {
+        true => len,
+        false => len - 1,
+    };
+
+    let mut points = Vec::with_capacity(segment_count * SPLINE_SUBDIVISIONS + 1);
+    for segment in 0..segment_count as isize {
+        let (p0, p1, p2, p3) = (
+            get(segment - 1),
+            get(segment),
+            get(segment + 1),
+            get(segment + 2),
+        );
+
+        for step in 0..SPLINE_SUBDIVISIONS {
+            let t = step as f32 / SPLINE_SUBDIVISIONS as f32;
+            let t2 = t * t;
+            let t3 = t2 * t;
+
+            points.push(
+                0.5 * (2.0 * p1
+                    + (p2 - p0) * t
+                    + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+                    + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3),
+            );
+        }
+    }
+
+    if !looping {
+        points.push(waypoints[len - 1]);
+    }
+
+    points
+}
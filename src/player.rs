@@ -1,17 +1,19 @@
 use bevy::prelude::*;
 use bevy_bvh_anim::prelude::*;
 use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::action::PlayerAction;
 use crate::bvh_manager::bvh_library::BvhLibrary;
 use crate::bvh_manager::bvh_player::{FrameData, JointMap};
 use crate::draw_axes::{ColorPalette, DrawAxes};
 use crate::motion::motion_player::MotionPlayerBundle;
+use crate::path_follow::PathFollower;
+use crate::record::Records;
 use crate::scene_loader::MainScene;
-use crate::trajectory::MovementDirection;
+use crate::trajectory::{MovementDirection, PrevTransform2d, Velocity};
 use crate::transform2d::Transform2d;
-use crate::ui::play_mode::RunPresetDirection;
-use crate::MainSet;
+use crate::{MainSet, MotionUpdate};
 
 pub struct PlayerPlugin;
 
@@ -21,13 +23,14 @@ impl Plugin for PlayerPlugin {
             .insert_resource(MovementConfig {
                 walk_speed: 2.0,
                 run_speed: 2.5,
+                sprint_speed: 3.5,
                 lerp_factor: 10.0,
             })
             .add_systems(
-                Update,
+                MotionUpdate,
                 (
-                    preset_movement_direction,
                     movement_direction,
+                    player_movement_input,
                     draw_player_direction,
                 )
                     .chain()
@@ -37,70 +40,16 @@ impl Plugin for PlayerPlugin {
     }
 }
 
-fn preset_movement_direction(
-    mut q_movement_directions: Query<&mut MovementDirection>,
-    time: Res<Time>,
-    movement_config: Res<MovementConfig>,
-    mut state: Local<(usize, f32)>,
-    run_preset_direction: Res<RunPresetDirection>,
-) {
-    let (current_direction, elapsed_time) = *state;
-
-    if **run_preset_direction == false {
-        return;
-    }
-
-    // move in circle
-    const DIRECTIONS: [Vec2; 4] = [
-        // Up
-        Vec2::new(0.0, 1.0),
-        // Right
-        Vec2::new(1.0, 0.0),
-        // Down
-        Vec2::new(0.0, -1.0),
-        // Left
-        Vec2::new(-1.0, 0.0),
-    ];
-
-    const DIRECTION_DURATIONS: [f32; 4] = [2.0, 2.0, 2.0, 2.0];
-
-    let new_elapsed_time = elapsed_time + time.delta_secs();
-
-    let current_direction_duration = DIRECTION_DURATIONS[current_direction];
-
-    let mut new_direction = current_direction;
-    let mut reset_time = new_elapsed_time;
-
-    if new_elapsed_time >= current_direction_duration {
-        new_direction = (current_direction + 1) % DIRECTIONS.len();
-        reset_time = 0.0;
-    }
-
-    *state = (new_direction, reset_time);
-
-    let direction = DIRECTIONS[new_direction];
-    for mut movement_direction in q_movement_directions.iter_mut() {
-        // **movement_direction = direction;
-
-        **movement_direction = Vec2::lerp(
-            **movement_direction,
-            direction,
-            f32::min(1.0, movement_config.lerp_factor * time.delta_secs()),
-        );
-    }
-}
-
 fn movement_direction(
-    mut q_movement_directions: Query<&mut MovementDirection>,
+    mut q_movement_directions: Query<
+        &mut MovementDirection,
+        (With<PlayerMarker>, Without<PathFollower>),
+    >,
     movement_config: Res<MovementConfig>,
     action: Res<ActionState<PlayerAction>>,
     time: Res<Time>,
     q_camera: Query<&Transform, With<Camera>>,
-    run_preset_direction: Res<RunPresetDirection>,
 ) {
-    if **run_preset_direction {
-        return;
-    }
     let camera_transform = q_camera.single();
     let mut action_axis = action
         .clamped_axis_pair(&PlayerAction::Walk)
@@ -120,6 +69,27 @@ fn movement_direction(
     }
 }
 
+/// Drive the [`MovementInput`] of players from the [`PlayerAction`]s.
+fn player_movement_input(
+    mut q_movement_inputs: Query<&mut MovementInput, (With<PlayerMarker>, Without<PathFollower>)>,
+    action: Res<ActionState<PlayerAction>>,
+) {
+    let tier = if action.pressed(&PlayerAction::Sprint) {
+        SpeedTier::Sprint
+    } else if action.pressed(&PlayerAction::Run) {
+        SpeedTier::Run
+    } else {
+        SpeedTier::Walk
+    };
+
+    for mut movement_input in q_movement_inputs.iter_mut() {
+        *movement_input = MovementInput {
+            moving: action.axis_pair(&PlayerAction::Walk) != Vec2::ZERO,
+            tier,
+        };
+    }
+}
+
 fn draw_player_direction(
     q_transform2ds: Query<&Transform2d, With<PlayerMarker>>,
     mut draw_axes: ResMut<DrawAxes>,
@@ -137,13 +107,15 @@ fn draw_player_direction(
     }
 }
 
-fn reset_player(
+pub(crate) fn reset_player(
     mut commands: Commands,
     bvh_library: Res<BvhLibrary>,
     bvh_assets: Res<Assets<BvhAsset>>,
     mut evr_reset_player: EventReader<ResetPlayer>,
     mut q_transforms: Query<&mut Transform>,
     q_scene: Query<(&JointMap, Entity), With<MainScene>>,
+    mut q_transform2d_records: Query<&mut Records<Transform2d>>,
+    mut q_velocity_records: Query<&mut Records<Velocity>>,
 ) {
     let Some(map) = bvh_library.get_map().and_then(|bvh| bvh_assets.get(bvh)) else {
         return;
@@ -157,11 +129,27 @@ fn reset_player(
         );
 
         for (joint_map, entity) in q_scene.iter() {
-            commands.entity(entity).insert((
-                PlayerBundle::default(),
-                Transform2d::default(),
-                MotionPlayerBundle::default(),
-            ));
+            // Remove first, so that the state initialized on `Added<MotionPlayer>` is reset too.
+            commands
+                .entity(entity)
+                .remove::<MotionPlayerBundle>()
+                .insert((
+                    PlayerBundle::default(),
+                    Transform2d::default(),
+                    PrevTransform2d::default(),
+                    Velocity::default(),
+                    MovementDirection::default(),
+                    MovementSpeed::default(),
+                    MovementInput::default(),
+                    MotionPlayerBundle::default(),
+                ));
+
+            if let Ok(mut records) = q_transform2d_records.get_mut(entity) {
+                records.iter_mut().for_each(|record| *record = default());
+            }
+            if let Ok(mut records) = q_velocity_records.get_mut(entity) {
+                records.iter_mut().for_each(|record| *record = default());
+            }
 
             for joint in map.joints() {
                 let joint_data = joint.data();
@@ -192,12 +180,12 @@ fn reset_player(
 #[derive(Bundle, Default)]
 pub struct PlayerBundle {
     pub marker: PlayerMarker,
-    pub movement_speed: MovementSpeed,
 }
 
 #[derive(Component, Default)]
 pub struct PlayerMarker;
 
+/// Current speed of an entity, accelerating towards the speed of its [`SpeedTier`].
 #[derive(Component, Default, Deref, DerefMut, Clone, Copy)]
 pub struct MovementSpeed(f32);
 
@@ -207,12 +195,40 @@ impl MovementSpeed {
     }
 }
 
+/// Movement requested by the input source of an entity (player input, a path follower, ...).
+#[derive(Component, Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+pub struct MovementInput {
+    /// Is the entity asked to move?
+    pub moving: bool,
+    pub tier: SpeedTier,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeedTier {
+    #[default]
+    Walk,
+    Run,
+    Sprint,
+}
+
 #[derive(Resource, Debug)]
 pub struct MovementConfig {
     pub walk_speed: f32,
     pub run_speed: f32,
+    pub sprint_speed: f32,
     pub lerp_factor: f32,
 }
 
+impl MovementConfig {
+    /// Target speed of a [`SpeedTier`].
+    pub fn speed(&self, tier: SpeedTier) -> f32 {
+        match tier {
+            SpeedTier::Walk => self.walk_speed,
+            SpeedTier::Run => self.run_speed,
+            SpeedTier::Sprint => self.sprint_speed,
+        }
+    }
+}
+
 #[derive(Event, Default, Clone, Copy)]
 pub struct ResetPlayer;
@@ -4,7 +4,7 @@ use std::marker::PhantomData;
 
 use bevy::prelude::*;
 
-use crate::MainSet;
+use crate::{MainSet, MotionUpdate};
 
 #[derive(Default)]
 pub struct RecordPlugin<T: Recordable>(PhantomData<T>);
@@ -12,7 +12,7 @@ pub struct RecordPlugin<T: Recordable>(PhantomData<T>);
 impl<T: Recordable> Plugin for RecordPlugin<T> {
     fn build(&self, app: &mut App) {
         app.add_systems(
-            Update,
+            MotionUpdate,
             (record_len::<T>, record::<T>)
                 .chain()
                 .in_set(MainSet::Record),
@@ -0,0 +1,392 @@
+//! Record player input along with the motion matching decisions, and replay the input
+//! to find where the decisions diverge.
+
+use std::io::Write;
+
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::action::PlayerAction;
+use crate::motion::motion_player::JumpToPose;
+use crate::motion_matching::NearestTrajectories;
+use crate::player::{reset_player, MovementInput, PlayerMarker, ResetPlayer, SpeedTier};
+use crate::timestep::MotionTimestep;
+use crate::trajectory::MovementDirection;
+use crate::{MainSet, MotionUpdate, LARGE_EPSILON};
+
+pub const REPLAY_PATH: &str = "replay.json";
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Replay>()
+            .add_event::<ReplayControl>()
+            .add_systems(Last, handle_replay_controls.before(reset_player))
+            .add_systems(
+                MotionUpdate,
+                (
+                    apply_replay_input
+                        .after(MainSet::Action)
+                        .before(MainSet::Record),
+                    record_replay_frame.after(MainSet::Animation),
+                ),
+            );
+    }
+}
+
+/// Start and stop recording or replaying, resetting the player at the start of both.
+fn handle_replay_controls(
+    mut replay: ResMut<Replay>,
+    mut control_evr: EventReader<ReplayControl>,
+    mut reset_evw: EventWriter<ResetPlayer>,
+    mut motion_timestep: ResMut<MotionTimestep>,
+    mut fixed_time: ResMut<Time<Fixed>>,
+) {
+    for control in control_evr.read() {
+        match control {
+            ReplayControl::StartRecording => {
+                let fixed_hz = (*motion_timestep == MotionTimestep::Fixed)
+                    .then(|| fixed_time.timestep().as_secs_f64().recip());
+                if fixed_hz.is_none() {
+                    warn!("Recording with a variable timestep, the replay may diverge.");
+                }
+
+                *replay = Replay::Recording(ReplayFile {
+                    fixed_hz,
+                    frames: Vec::new(),
+                });
+                reset_evw.send(ResetPlayer);
+            }
+            ReplayControl::StopRecording => {
+                if let Replay::Recording(file) = &*replay {
+                    match file.save(REPLAY_PATH) {
+                        Ok(()) => info!(
+                            "Saved {} recorded frames to {REPLAY_PATH}.",
+                            file.frames.len()
+                        ),
+                        Err(err) => error!("Could not save recording: {err}"),
+                    }
+                }
+                *replay = Replay::Idle;
+            }
+            ReplayControl::StartReplay => {
+                let file = match ReplayFile::load(REPLAY_PATH) {
+                    Ok(file) => file,
+                    Err(err) => {
+                        error!("Could not load recording: {err}");
+                        continue;
+                    }
+                };
+
+                // Replay with the timestep the recording was made with.
+                match file.fixed_hz {
+                    Some(fixed_hz) => {
+                        *motion_timestep = MotionTimestep::Fixed;
+                        fixed_time.set_timestep_hz(fixed_hz);
+                    }
+                    None => *motion_timestep = MotionTimestep::Variable,
+                }
+
+                *replay = Replay::Replaying {
+                    file,
+                    step: 0,
+                    divergences: Vec::new(),
+                };
+                reset_evw.send(ResetPlayer);
+            }
+            ReplayControl::StopReplay => *replay = Replay::Idle,
+        }
+    }
+}
+
+/// Feed the recorded input back in place of the player's input.
+fn apply_replay_input(
+    replay: Res<Replay>,
+    mut action: ResMut<ActionState<PlayerAction>>,
+    mut q_players: Query<(&mut MovementDirection, &mut MovementInput), With<PlayerMarker>>,
+) {
+    let Replay::Replaying { file, step, .. } = &*replay else {
+        return;
+    };
+
+    let Some(frame) = file.frames.get(*step) else {
+        return;
+    };
+
+    action.set_axis_pair(&PlayerAction::Walk, frame.input.walk);
+    for (tier, player_action) in [
+        (SpeedTier::Run, PlayerAction::Run),
+        (SpeedTier::Sprint, PlayerAction::Sprint),
+    ] {
+        match frame.input.movement_input.tier == tier {
+            true => action.press(&player_action),
+            false => action.release(&player_action),
+        }
+    }
+
+    // Same player as the one being recorded in `record_replay_frame`.
+    if let Some((mut movement_direction, mut movement_input)) = q_players.iter_mut().next() {
+        **movement_direction = frame.input.movement_direction;
+        *movement_input = frame.input.movement_input;
+    }
+}
+
+/// Record the input and decisions of this step, or diff the decisions against the recording.
+///
+/// Only a single player is supported, others are ignored.
+fn record_replay_frame(
+    mut replay: ResMut<Replay>,
+    action: Res<ActionState<PlayerAction>>,
+    q_players: Query<(&MovementDirection, &MovementInput, Entity), With<PlayerMarker>>,
+    mut nearest_trajectories_evr: EventReader<NearestTrajectories>,
+    mut jump_evr: EventReader<JumpToPose>,
+    mut warned_multiple_players: Local<bool>,
+) {
+    let mut players = q_players.iter();
+    let Some((movement_direction, movement_input, entity)) = players.next() else {
+        nearest_trajectories_evr.clear();
+        jump_evr.clear();
+        return;
+    };
+
+    if players.next().is_some() && !matches!(*replay, Replay::Idle) && !*warned_multiple_players {
+        warn!("Only the first player ({entity}) is recorded and replayed.");
+        *warned_multiple_players = true;
+    }
+
+    let mut decisions = nearest_trajectories_evr
+        .read()
+        .filter(|trajs| trajs.entity == entity)
+        .map(|trajs| {
+            Decision::NearestTrajectories(
+                trajs
+                    .iter()
+                    .map(|traj| (traj.chunk_index, traj.chunk_offset, traj.distance))
+                    .collect(),
+            )
+        })
+        .collect::<Vec<_>>();
+    decisions.extend(
+        jump_evr
+            .read()
+            .filter(|jump| jump.entity == entity)
+            .map(|jump| Decision::JumpToPose {
+                chunk_index: jump.chunk_index,
+                time: jump.time,
+            }),
+    );
+
+    match &mut *replay {
+        Replay::Idle => {}
+        Replay::Recording(file) => file.frames.push(ReplayFrame {
+            input: InputFrame {
+                walk: action.axis_pair(&PlayerAction::Walk),
+                movement_direction: **movement_direction,
+                movement_input: *movement_input,
+            },
+            decisions,
+        }),
+        Replay::Replaying {
+            file,
+            step,
+            divergences,
+        } => {
+            let Some(frame) = file.frames.get(*step) else {
+                return;
+            };
+
+            let matches = frame.decisions.len() == decisions.len()
+                && frame
+                    .decisions
+                    .iter()
+                    .zip(decisions.iter())
+                    .all(|(recorded, replayed)| recorded.approx_eq(replayed));
+
+            if !matches {
+                warn!("Replay diverged at step {}.", *step);
+                divergences.push(Divergence {
+                    step: *step,
+                    recorded: frame.decisions.clone(),
+                    replayed: decisions,
+                });
+            }
+
+            *step += 1;
+        }
+    }
+}
+
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayControl {
+    StartRecording,
+    /// Stop recording and save it to [`REPLAY_PATH`].
+    StopRecording,
+    /// Load the recording from [`REPLAY_PATH`] and replay it.
+    StartReplay,
+    StopReplay,
+}
+
+#[derive(Resource, Default, Debug)]
+pub enum Replay {
+    #[default]
+    Idle,
+    Recording(ReplayFile),
+    Replaying {
+        file: ReplayFile,
+        /// Index of the next frame to replay.
+        step: usize,
+        /// Steps where the decisions differ from the recording.
+        divergences: Vec<Divergence>,
+    },
+}
+
+/// Decisions of a step that differ from the recording.
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    pub step: usize,
+    pub recorded: Vec<Decision>,
+    pub replayed: Vec<Decision>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ReplayFile {
+    /// Fixed timestep in Hz the recording was made with, [`None`] for a variable timestep.
+    pub fixed_hz: Option<f64>,
+    /// One frame per step of the [`MotionUpdate`] schedule.
+    pub frames: Vec<ReplayFrame>,
+}
+
+impl ReplayFile {
+    pub fn save(&self, path: &str) -> Result<(), ReplayError> {
+        let mut file = std::fs::File::create(path)?;
+        file.write_all(serde_json::to_string(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self, ReplayError> {
+        let bytes = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplayFrame {
+    pub input: InputFrame,
+    pub decisions: Vec<Decision>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct InputFrame {
+    /// Axis pair of [`PlayerAction::Walk`].
+    pub walk: Vec2,
+    /// [`MovementDirection`] of the player, which also depends on the camera.
+    pub movement_direction: Vec2,
+    pub movement_input: MovementInput,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Decision {
+    /// Candidates of a [`NearestTrajectories`] search as `(chunk_index, chunk_offset, distance)`.
+    NearestTrajectories(Vec<(usize, usize, f32)>),
+    /// The pose that was jumped to.
+    JumpToPose { chunk_index: usize, time: f32 },
+}
+
+impl Decision {
+    /// Equal up to [`LARGE_EPSILON`] for distances and times.
+    pub fn approx_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::NearestTrajectories(lhs), Self::NearestTrajectories(rhs)) => {
+                lhs.len() == rhs.len()
+                    && lhs.iter().zip(rhs.iter()).all(|(lhs, rhs)| {
+                        lhs.0 == rhs.0 && lhs.1 == rhs.1 && f32::abs(lhs.2 - rhs.2) < LARGE_EPSILON
+                    })
+            }
+            (
+                Self::JumpToPose {
+                    chunk_index: lhs_chunk_index,
+                    time: lhs_time,
+                },
+                Self::JumpToPose {
+                    chunk_index: rhs_chunk_index,
+                    time: rhs_time,
+                },
+            ) => {
+                lhs_chunk_index == rhs_chunk_index && f32::abs(lhs_time - rhs_time) < LARGE_EPSILON
+            }
+            _ => false,
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("Could not access replay file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not (de)serialize using serde: {0}")]
+    Serde(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::path_follow::PathFollower;
+    use crate::test_app::{motion_app, run_for};
+
+    use super::*;
+
+    const FIXED_STEP: Duration = Duration::from_millis(16);
+
+    #[test]
+    fn replaying_a_recording_does_not_diverge() {
+        let duration = FIXED_STEP * 300;
+        let path = std::env::temp_dir().join(format!("replay_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        // Record the player following its path.
+        let (mut app, _) = motion_app(FIXED_STEP);
+        app.insert_resource(Replay::Recording(ReplayFile {
+            fixed_hz: Some(FIXED_STEP.as_secs_f64().recip()),
+            frames: Vec::new(),
+        }));
+        run_for(&mut app, FIXED_STEP, duration);
+
+        let Replay::Recording(file) = app.world().resource::<Replay>() else {
+            panic!("The recording should not have stopped.");
+        };
+        assert_eq!(file.frames.len(), 300);
+        assert!(file
+            .frames
+            .iter()
+            .flat_map(|frame| frame.decisions.iter())
+            .any(|decision| matches!(decision, Decision::JumpToPose { .. })));
+        file.save(path).unwrap();
+
+        // Replay the recorded input on a fresh app, without the path.
+        let file = ReplayFile::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let (mut app, player) = motion_app(FIXED_STEP);
+        app.world_mut().entity_mut(player).remove::<PathFollower>();
+        app.insert_resource(Replay::Replaying {
+            file,
+            step: 0,
+            divergences: Vec::new(),
+        });
+        run_for(&mut app, FIXED_STEP, duration);
+
+        let Replay::Replaying {
+            step, divergences, ..
+        } = app.world().resource::<Replay>()
+        else {
+            panic!("The replay should not have stopped.");
+        };
+        assert_eq!(*step, 300);
+        assert!(divergences.is_empty(), "{divergences:?}");
+    }
+}
@@ -3,7 +3,10 @@ use bevy::prelude::*;
 use bevy::render::mesh::VertexAttributeValues;
 
 use crate::draw_axes::ColorPalette;
+use crate::ground::Ground;
+use crate::motion::foot_ik::{FootIkConfig, LegConfig};
 use crate::motion::motion_player::MotionPlayerBundle;
+use crate::motion_matching::search_schedule::SearchSchedule;
 use crate::player::PlayerBundle;
 use crate::trajectory::TrajectoryBundle;
 
@@ -12,7 +15,15 @@ pub struct SceneLoaderPlugin;
 
 impl Plugin for SceneLoaderPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, (spawn_scene, spawn_light, spawn_ground));
+        app.insert_resource(FootIkConfig {
+            // Leg joints of the skeleton loaded in `spawn_scene`.
+            legs: vec![
+                LegConfig::new("Model_LeftUpLeg", "Model_LeftLeg", "Model_LeftFoot"),
+                LegConfig::new("Model_RightUpLeg", "Model_RightLeg", "Model_RightFoot"),
+            ],
+            ..default()
+        })
+        .add_systems(Startup, (spawn_scene, spawn_light, spawn_ground));
     }
 }
 
@@ -27,6 +38,7 @@ fn spawn_scene(mut commands: Commands, asset_server: Res<AssetServer>) {
         PlayerBundle::default(),
         TrajectoryBundle::new(100),
         MotionPlayerBundle::default(),
+        SearchSchedule::default(),
     ));
 }
 
@@ -85,6 +97,31 @@ fn spawn_ground(
             ..default()
         })),
         GroundPlane,
+        Ground,
+    ));
+
+    // Uneven terrain to walk on.
+    let terrain_material = materials.add(StandardMaterial {
+        base_color: palette.base4,
+        ..default()
+    });
+
+    // Steps.
+    for (i, height) in [0.1, 0.2, 0.3].into_iter().enumerate() {
+        commands.spawn((
+            Mesh3d(meshes.add(Cuboid::new(1.0, height * 2.0, 2.0))),
+            MeshMaterial3d(terrain_material.clone()),
+            Transform::from_xyz(3.0 + i as f32, 0.0, 0.0),
+            Ground,
+        ));
+    }
+
+    // Slope.
+    commands.spawn((
+        Mesh3d(meshes.add(Cuboid::new(2.0, 0.2, 4.0))),
+        MeshMaterial3d(terrain_material),
+        Transform::from_xyz(-3.0, 0.0, 0.0).with_rotation(Quat::from_rotation_x(0.15)),
+        Ground,
     ));
 }
 
@@ -0,0 +1,150 @@
+//! Headless [`App`] running the motion matching pipeline on the bundled motion data.
+
+use std::time::Duration;
+
+use bevy::animation::AnimationPlugin;
+use bevy::gizmos::GizmoPlugin;
+use bevy::prelude::*;
+use bevy::render::render_resource::Shader;
+use bevy::state::app::StatesPlugin;
+use bevy::time::TimeUpdateStrategy;
+use leafwing_input_manager::prelude::*;
+
+use crate::action::PlayerAction;
+use crate::bvh_manager::bvh_player::JointMap;
+use crate::draw_axes::DrawAxesPlugin;
+use crate::motion::motion_asset::MotionAsset;
+use crate::motion::motion_player::MotionPlayerBundle;
+use crate::motion::{MotionHandle, MotionPlugin};
+use crate::motion_matching::search_schedule::SearchSchedule;
+use crate::motion_matching::MotionMatchingPlugin;
+use crate::path_follow::{PathFollowPlugin, PathFollower, PathInterpolation};
+use crate::player::{MovementConfig, PlayerBundle, ResetPlayer};
+use crate::record::RecordPlugin;
+use crate::replay::ReplayPlugin;
+use crate::timestep::{MotionTimestep, TimestepPlugin};
+use crate::trajectory::{TrajectoryBundle, TrajectoryPlugin, Velocity};
+use crate::transform2d::{Transform2d, Transform2dPlugin};
+use crate::ui::config::DrawTrajectory;
+use crate::ui::play_mode::{MotionMatchingResult, RunPresetDirection};
+use crate::{GameMode, MainSet, Method, MotionUpdate};
+
+/// Headless app playing the motion data in steps of `fixed_step`, with a player following a path.
+///
+/// Time does not advance until [`run_for`] is called.
+pub(crate) fn motion_app(fixed_step: Duration) -> (App, Entity) {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        AssetPlugin::default(),
+        StatesPlugin,
+        TransformPlugin,
+        HierarchyPlugin,
+    ))
+    // Gizmos are drawn by a few systems but never rendered.
+    .init_asset::<Shader>()
+    .add_plugins((GizmoPlugin, AnimationPlugin))
+    .configure_sets(
+        MotionUpdate,
+        (
+            MainSet::Action,
+            MainSet::Record,
+            MainSet::Trajectory,
+            MainSet::MotionMatching,
+            MainSet::Animation,
+        )
+            .chain(),
+    )
+    .add_plugins((
+        Transform2dPlugin,
+        RecordPlugin::<Transform2d>::default(),
+        RecordPlugin::<Velocity>::default(),
+        TrajectoryPlugin,
+        MotionPlugin,
+        MotionMatchingPlugin,
+        DrawAxesPlugin,
+        TimestepPlugin,
+        PathFollowPlugin,
+        ReplayPlugin,
+    ))
+    .init_state::<GameMode>()
+    .init_state::<Method>()
+    .insert_resource(RunPresetDirection(false))
+    .init_resource::<DrawTrajectory>()
+    .init_resource::<MotionMatchingResult>()
+    .init_resource::<ActionState<PlayerAction>>()
+    .add_event::<ResetPlayer>()
+    .insert_resource(MovementConfig {
+        walk_speed: 2.0,
+        run_speed: 2.5,
+        sprint_speed: 3.5,
+        lerp_factor: 10.0,
+    })
+    .insert_resource(MotionTimestep::Fixed)
+    .insert_resource(Time::<Fixed>::from_duration(fixed_step))
+    .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::ZERO));
+
+    // Time does not advance while the motion data loads.
+    let mut joint_names = None;
+    for _ in 0..1000 {
+        app.update();
+
+        let world = app.world();
+        let motion_asset = world
+            .get_resource::<MotionHandle>()
+            .and_then(|handle| world.resource::<Assets<MotionAsset>>().get(&**handle));
+        if let Some(motion_asset) = motion_asset {
+            joint_names = Some(
+                motion_asset
+                    .joints()
+                    .iter()
+                    .map(|joint| joint.name().to_string())
+                    .collect::<Vec<_>>(),
+            );
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let joint_names = joint_names.expect("Motion data should be loaded.");
+
+    let world = app.world_mut();
+    let mut joint_map = JointMap::default();
+    for name in joint_names {
+        joint_map.insert(name, world.spawn(Transform::default()).id());
+    }
+
+    let waypoints = vec![
+        Vec2::ZERO,
+        Vec2::new(0.0, 4.0),
+        Vec2::new(4.0, 4.0),
+        Vec2::new(4.0, 0.0),
+    ];
+    let player = world
+        .spawn((
+            PlayerBundle::default(),
+            TrajectoryBundle::new(100),
+            MotionPlayerBundle::default(),
+            SearchSchedule::default(),
+            Transform::default(),
+            PathFollower::new(waypoints, PathInterpolation::CatmullRom, true),
+        ))
+        .id();
+    for &joint in joint_map.values() {
+        world.entity_mut(joint).set_parent(player);
+    }
+    world.entity_mut(player).insert(joint_map);
+
+    world
+        .resource_mut::<NextState<GameMode>>()
+        .set(GameMode::Play);
+
+    (app, player)
+}
+
+/// Run the app for `duration` with frames of `frame_delta`.
+pub(crate) fn run_for(app: &mut App, frame_delta: Duration, duration: Duration) {
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(frame_delta));
+    for _ in 0..duration.as_nanos() / frame_delta.as_nanos() {
+        app.update();
+    }
+}
@@ -2,31 +2,29 @@ use std::{fs::File, io::Write};
 
 use bevy::{ecs::system::SystemState, prelude::*};
 use bevy_egui::egui;
-use clustering::{kmeans, Centroid};
-use kdtree::{distance::squared_euclidean, KdTree};
 use serde::{Deserialize, Serialize};
 
 use crate::motion::chunk::ChunkIterator;
 use crate::motion::MotionData;
-use crate::motion_matching::kdtree_match::offset_distance;
-use crate::motion_matching::kmeans_match::KMeansResource;
-use crate::motion_matching::{MatchConfig, MatchTrajectory, TrajectoryMatch};
+use crate::motion_matching::kdtree_match::KdTreeIndex;
+use crate::motion_matching::kmeans_match::{KMeansConfig, KMeansIndex};
+use crate::motion_matching::{MatchConfig, MatchTrajectory, PlayableOffsets, TrajectoryMatch};
 use crate::trajectory::{Trajectory, TrajectoryConfig};
 use crate::BVH_SCALE_RATIO;
 
+/// K-means settings the accuracy report is measured with.
+const TESTING_KMEANS_CONFIG: KMeansConfig = KMeansConfig {
+    k: 20,
+    max_iter: 150,
+    probe_count: 3,
+};
+
 pub struct TestingPlugin;
 
 impl Plugin for TestingPlugin {
     fn build(&self, app: &mut App) {
         app.init_state::<TestingState>()
             .init_resource::<TestingData>()
-            .add_systems(
-                PreUpdate,
-                (
-                    populate_kmeans::<20, 150>.run_if(not(resource_exists::<KMeansResource>)),
-                    populate_kdtree.run_if(not(resource_exists::<KdTreeStructure>)),
-                ),
-            )
             .init_resource::<NearestTrajectory>()
             .add_systems(OnEnter(TestingState::Loading), load_testing_data)
             .add_systems(
@@ -36,8 +34,8 @@ impl Plugin for TestingPlugin {
             .add_systems(
                 OnEnter(TestingState::Loaded),
                 (
-                    traj_matching_with_kdtree.run_if(resource_exists::<KdTreeStructure>),
-                    traj_matching_with_kmeans.run_if(resource_exists::<KMeansStructure>),
+                    traj_matching_with_kdtree,
+                    traj_matching_with_kmeans,
                     traj_matching_with_knn,
                 )
                     .chain(),
@@ -189,66 +187,24 @@ fn distance(lhs: &[Vec2], rhs: &[Vec2]) -> f32 {
     offset_distance
 }
 
-fn populate_kdtree(
-    mut commands: Commands,
+fn traj_matching_with_kdtree(
     motion_data: MotionData,
+    match_config: Res<MatchConfig>,
     trajectory_config: Res<TrajectoryConfig>,
+    mut nearest_trajectories: ResMut<NearestTrajectory>,
+    test_data: Res<TestData>,
 ) {
-    let Some(motion_data) = motion_data.get() else {
+    let Some(motion_asset) = motion_data.get() else {
         return;
     };
+    // Built here so the comparison does not depend on the selected search method.
+    let kd_tree = KdTreeIndex::build(motion_asset, &trajectory_config);
+    // Compare against every trajectory, same as the kNN search.
+    let playable_offsets = PlayableOffsets::new(motion_asset, 0.0);
 
-    let num_segments = trajectory_config.num_segments();
-    let num_points = trajectory_config.num_points();
-
-    let mut kdtree = KdTree::new(num_segments * 2);
-
-    // Populate KD-Tree with motion data
-    for (chunk_index, chunk) in motion_data.trajectory_data.iter_chunk().enumerate() {
-        let num_trajectories = chunk.len() - num_segments;
-
-        for chunk_offset in 0..num_trajectories {
-            let data_traj = &chunk[chunk_offset..chunk_offset + num_points];
-            let data_inv_matrix = data_traj[trajectory_config.history_count].matrix.inverse();
-
-            let data_traj = data_traj
-                .iter()
-                .map(|point| {
-                    let (.., translation) = point.matrix.to_scale_rotation_translation();
-                    data_inv_matrix.transform_point3(translation).xz()
-                })
-                .collect::<Vec<_>>();
-
-            let mut traj_offsets = Vec::new();
-            // Add each offset from the trajectory to the KD-Tree
-            for i in 1..data_traj.len() {
-                let offset = (data_traj[i] - data_traj[i - 1]) * BVH_SCALE_RATIO;
-                traj_offsets.push(offset.x);
-                traj_offsets.push(offset.y);
-            }
-
-            kdtree
-                .add(traj_offsets, (chunk_index, chunk_offset))
-                .unwrap();
-        }
-    }
-    commands.insert_resource(KdTreeStructure(kdtree));
-}
-
-fn traj_matching_with_kdtree(
-    match_config: Res<MatchConfig>,
-    kd_tree: Res<KdTreeStructure>,
-    mut nearest_trajectories: ResMut<NearestTrajectory>,
-    test_data: Res<TestData>,
-) {
     let mut nearest_trajs = Vec::new();
 
     for traj in test_data.iter() {
-        let mut nearest_traj = MatchTrajectory {
-            distance: f32::MAX,
-            chunk_index: 0,
-            chunk_offset: 0,
-        };
         let mut traj_offsets = Vec::new();
         // Create trajectory offset.
         for i in 1..traj.len() {
@@ -257,101 +213,44 @@ fn traj_matching_with_kdtree(
             traj_offsets.push(offset.y);
         }
 
-        if let Ok(results) = kd_tree.nearest(
-            &traj_offsets,
-            match_config.max_match_count,
-            &squared_euclidean,
-        ) {
-            for (distance, &(chunk_index, chunk_offset)) in results {
-                if distance < match_config.match_threshold && distance < nearest_traj.distance {
-                    nearest_traj = MatchTrajectory {
-                        distance,
-                        chunk_index,
-                        chunk_offset,
-                    };
-                }
-            }
-        }
+        let nearest_traj = kd_tree
+            .nearest(
+                &traj_offsets,
+                1,
+                match_config.match_threshold,
+                &playable_offsets,
+            )
+            .first()
+            .copied()
+            .unwrap_or(MatchTrajectory {
+                distance: f32::MAX,
+                chunk_index: 0,
+                chunk_offset: 0,
+            });
+
         nearest_trajs.push(nearest_traj);
     }
     nearest_trajectories.kdtree = nearest_trajs;
 }
 
-fn populate_kmeans<const K: usize, const MAX_ITER: usize>(
-    mut commands: Commands,
+fn traj_matching_with_kmeans(
     motion_data: MotionData,
+    match_config: Res<MatchConfig>,
     trajectory_config: Res<TrajectoryConfig>,
+    mut nearest_trajectories: ResMut<NearestTrajectory>,
+    test_data: Res<TestData>,
 ) {
-    let Some(motion_data) = motion_data.get() else {
+    let Some(motion_asset) = motion_data.get() else {
         return;
     };
+    // Built here so the comparison does not depend on the selected search method.
+    let kmeans = KMeansIndex::build(motion_asset, &trajectory_config, &TESTING_KMEANS_CONFIG);
+    // Compare against every trajectory, same as the kNN search.
+    let playable_offsets = PlayableOffsets::new(motion_asset, 0.0);
 
-    let num_segments = trajectory_config.num_segments();
-    let num_points = trajectory_config.num_points();
-
-    let mut trajectory_offsets = Vec::new();
-
-    for (chunk_index, chunk) in motion_data.trajectory_data.iter_chunk().enumerate() {
-        let num_trajectories = chunk.len() - num_segments;
-
-        for chunk_offset in 0..num_trajectories {
-            let data_traj = &chunk[chunk_offset..chunk_offset + num_points];
-            let data_inv_matrix = data_traj[trajectory_config.history_count].matrix.inverse();
-
-            let data_traj = data_traj
-                .iter()
-                .map(|point| {
-                    let (.., translation) = point.matrix.to_scale_rotation_translation();
-                    data_inv_matrix.transform_point3(translation).xz()
-                })
-                .collect::<Vec<_>>();
-
-            let mut traj_offsets = Vec::new();
-            for i in 1..data_traj.len() {
-                let offset = (data_traj[i] - data_traj[i - 1]) * BVH_SCALE_RATIO;
-                traj_offsets.push(offset.x);
-                traj_offsets.push(offset.y);
-            }
-
-            trajectory_offsets.push((traj_offsets, chunk_index, chunk_offset));
-        }
-    }
-
-    let data: Vec<Vec<f64>> = trajectory_offsets
-        .iter()
-        .map(|(offsets, _, _)| offsets.iter().map(|&x| x as f64).collect())
-        .collect();
-
-    let clustering = kmeans(K, &data, MAX_ITER);
-
-    let mut cluster_members: Vec<Vec<(usize, usize, Vec<f32>)>> = vec![Vec::new(); K];
-
-    for (i, cluster_id) in clustering.membership.iter().enumerate() {
-        let (offsets, chunk_index, chunk_offset) = &trajectory_offsets[i];
-        cluster_members[*cluster_id].push((*chunk_index, *chunk_offset, offsets.clone()));
-    }
-
-    commands.insert_resource(KMeansStructure {
-        centroids: clustering.centroids,
-        cluster_members,
-    })
-}
-
-fn traj_matching_with_kmeans(
-    match_config: Res<MatchConfig>,
-    kmeans: Res<KMeansStructure>,
-    mut nearest_trajectories: ResMut<NearestTrajectory>,
-    test_data: Res<TestData>,
-) {
     let mut nearest_trajs = Vec::new();
 
     for traj in test_data.iter() {
-        let mut nearest_traj = MatchTrajectory {
-            distance: f32::MAX,
-            chunk_index: 0,
-            chunk_offset: 0,
-        };
-
         let mut traj_offsets = Vec::new();
 
         for i in 1..traj.len() {
@@ -360,37 +259,21 @@ fn traj_matching_with_kmeans(
             traj_offsets.push(offset.y);
         }
 
-        let mut nearest_centroids = Vec::new();
-        for (i, centroid) in kmeans.centroids.iter().enumerate() {
-            let centroid_f32: Vec<f32> = centroid.0.iter().map(|&x| x as f32).collect();
-            let distance = offset_distance(&traj_offsets, &centroid_f32);
-
-            if distance <= match_config.match_threshold {
-                nearest_centroids.push((distance, i));
-            } else {
-                continue;
-            }
-        }
-
-        for (_distance, centroid_index) in nearest_centroids {
-            if let Some(members) = kmeans.cluster_members.get(centroid_index) {
-                for (chunk_index, chunk_offset, offsets) in members {
-                    let distance = offset_distance(&traj_offsets, offsets);
-
-                    if distance > match_config.match_threshold {
-                        continue;
-                    }
-
-                    if distance < nearest_traj.distance {
-                        nearest_traj = MatchTrajectory {
-                            distance,
-                            chunk_index: *chunk_index,
-                            chunk_offset: *chunk_offset,
-                        };
-                    }
-                }
-            }
-        }
+        let nearest_traj = kmeans
+            .nearest(
+                &traj_offsets,
+                TESTING_KMEANS_CONFIG.probe_count,
+                1,
+                match_config.match_threshold,
+                &playable_offsets,
+            )
+            .first()
+            .copied()
+            .unwrap_or(MatchTrajectory {
+                distance: f32::MAX,
+                chunk_index: 0,
+                chunk_offset: 0,
+            });
 
         nearest_trajs.push(nearest_traj);
     }
@@ -401,13 +284,6 @@ fn write_to_csv(test_data: Res<TestData>, nearest_trajectories: Res<NearestTraje
     let file = File::create("assets/traj_matching_result.csv").expect("Failed to create CSV file");
     let mut writer = csv::Writer::from_writer(file);
 
-    let mut kd_tree_chunk_index_score = 0;
-    let mut kd_tree_chunk_offset_score = 0;
-    let mut kmeans_chunk_index_score = 0;
-    let mut kmeans_chunk_offset_score = 0;
-
-    let data_count = nearest_trajectories.knn.len();
-
     writer
         .write_record(vec![
             "Trajectories".to_string(),
@@ -419,60 +295,65 @@ fn write_to_csv(test_data: Res<TestData>, nearest_trajectories: Res<NearestTraje
             "kMeans_chunk_offset".to_string(),
         ])
         .expect("Failed to write CSV headers");
-    for (i, traj_data) in test_data.iter().enumerate() {
-        let traj_str = format!("{:?}", traj_data);
-
-        if let (Some(knn), Some(kdtree), Some(kmeans)) = (
-            nearest_trajectories.knn.get(i),
-            nearest_trajectories.kdtree.get(i),
-            nearest_trajectories.kmeans.get(i),
-        ) {
-            if kdtree.chunk_index == knn.chunk_index {
-                kd_tree_chunk_index_score += 1;
-            }
-            if kdtree.chunk_offset == knn.chunk_offset {
-                kd_tree_chunk_offset_score += 1;
-            }
-            if kmeans.chunk_index == knn.chunk_index {
-                kmeans_chunk_index_score += 1;
-            }
-            if kmeans.chunk_offset == knn.chunk_offset {
-                kmeans_chunk_offset_score += 1;
-            }
 
-            writer
-                .write_record(&[
-                    traj_str,
-                    knn.chunk_index.to_string(),
-                    knn.chunk_offset.to_string(),
-                    kdtree.chunk_index.to_string(),
-                    kdtree.chunk_offset.to_string(),
-                    kmeans.chunk_index.to_string(),
-                    kmeans.chunk_offset.to_string(),
-                ])
-                .expect("Failed to write CSV record");
-            writer.flush().expect("Failed to flush CSV writer");
-        }
-    }
+    let to_cells = |traj: Option<&MatchTrajectory>| match traj {
+        Some(traj) => [traj.chunk_index.to_string(), traj.chunk_offset.to_string()],
+        None => [String::new(), String::new()],
+    };
 
-    // println!("kd_tree_chunk_index_score: {}", kd_tree_chunk_index_score);
-    // println!("kd_tree_chunk_offset_score: {}", kd_tree_chunk_offset_score);
-    // println!("kmeans_chunk_index_score: {}", kmeans_chunk_index_score);
-    // println!("kmeans_chunk_offset_score: {}", kmeans_chunk_offset_score);
+    for (i, traj_data) in test_data.iter().enumerate() {
+        let Some(knn) = nearest_trajectories.knn.get(i) else {
+            continue;
+        };
+        let [knn_index, knn_offset] = to_cells(Some(knn));
+        let [kdtree_index, kdtree_offset] = to_cells(nearest_trajectories.kdtree.get(i));
+        let [kmeans_index, kmeans_offset] = to_cells(nearest_trajectories.kmeans.get(i));
+
+        writer
+            .write_record(&[
+                format!("{:?}", traj_data),
+                knn_index,
+                knn_offset,
+                kdtree_index,
+                kdtree_offset,
+                kmeans_index,
+                kmeans_offset,
+            ])
+            .expect("Failed to write CSV record");
+    }
+    writer.flush().expect("Failed to flush CSV writer");
 
-    let kdtree_accuracy = (kd_tree_chunk_index_score as f64 + kd_tree_chunk_offset_score as f64)
-        / (data_count as f64 * 2.0)
-        * 100.0;
+    if let Some(kdtree_accuracy) = accuracy(&nearest_trajectories.knn, &nearest_trajectories.kdtree)
+    {
+        println!("kd_tree_accuracy: {:.2} %", kdtree_accuracy);
+    }
+    if let Some(kmeans_accuracy) = accuracy(&nearest_trajectories.knn, &nearest_trajectories.kmeans)
+    {
+        println!(
+            "kmeans_accuracy (k: {}, max_iter: {}): {:.2} %",
+            TESTING_KMEANS_CONFIG.k, TESTING_KMEANS_CONFIG.max_iter, kmeans_accuracy
+        );
+    }
+}
 
-    let kmeans_accuracy = (kmeans_chunk_index_score as f64 + kmeans_chunk_offset_score as f64)
-        / (data_count as f64 * 2.0)
-        * 100.0;
+/// Percentage of chunk indices and offsets in `matches` that agree with the `knn` ground truth.
+///
+/// Returns [`None`] when `matches` was not computed for every test trajectory.
+fn accuracy(knn: &[MatchTrajectory], matches: &[MatchTrajectory]) -> Option<f64> {
+    if knn.is_empty() || knn.len() != matches.len() {
+        return None;
+    }
 
-    println!("kd_tree_accuracy: {:.2} %", kdtree_accuracy);
-    println!(
-        "kmeans_accuracy (k: 20, max_iter: 150): {:.2} %",
-        kmeans_accuracy
-    );
+    let score = knn
+        .iter()
+        .zip(matches)
+        .map(|(knn, other)| {
+            (knn.chunk_index == other.chunk_index) as usize
+                + (knn.chunk_offset == other.chunk_offset) as usize
+        })
+        .sum::<usize>();
+
+    Some(score as f64 / (knn.len() as f64 * 2.0) * 100.0)
 }
 
 #[derive(Resource, Debug, Default, Deref, DerefMut, Serialize, Deserialize)]
@@ -495,12 +376,3 @@ enum TestingState {
     Loaded,
     Save,
 }
-
-#[derive(Resource, Deref, DerefMut)]
-struct KdTreeStructure(KdTree<f32, (usize, usize), Vec<f32>>);
-
-#[derive(Resource)]
-struct KMeansStructure {
-    centroids: Vec<Centroid>,
-    cluster_members: Vec<Vec<(usize, usize, Vec<f32>)>>,
-}
@@ -0,0 +1,206 @@
+//! Run the [`MotionUpdate`] schedule every frame or at a deterministic fixed timestep.
+
+use bevy::prelude::*;
+
+use crate::bvh_manager::bvh_player::JointMap;
+use crate::motion::motion_player::MotionPlayer;
+use crate::motion::MotionData;
+use crate::transform2d::{apply_transform2d, Transform2d};
+use crate::{MainSet, MotionUpdate};
+
+pub struct TimestepPlugin;
+
+impl Plugin for TimestepPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MotionTimestep>()
+            .insert_resource(Time::<Fixed>::from_hz(60.0))
+            .add_systems(
+                PreUpdate,
+                (
+                    init_fixed_step_snapshot,
+                    clear_fixed_step_snapshots.run_if(resource_changed::<MotionTimestep>),
+                ),
+            )
+            .add_systems(
+                Update,
+                run_motion_update.run_if(resource_equals(MotionTimestep::Variable)),
+            )
+            .add_systems(
+                FixedUpdate,
+                run_motion_update.run_if(resource_equals(MotionTimestep::Fixed)),
+            )
+            .add_systems(
+                MotionUpdate,
+                (
+                    restore_fixed_step.before(MainSet::Action),
+                    snapshot_fixed_step.after(MainSet::Animation),
+                )
+                    .run_if(resource_equals(MotionTimestep::Fixed)),
+            )
+            .add_systems(
+                PostUpdate,
+                interpolate_fixed_step
+                    .after(apply_transform2d)
+                    .before(TransformSystem::TransformPropagate)
+                    .run_if(resource_equals(MotionTimestep::Fixed)),
+            );
+    }
+}
+
+fn run_motion_update(world: &mut World) {
+    world.run_schedule(MotionUpdate);
+}
+
+fn init_fixed_step_snapshot(mut commands: Commands, q_players: Query<Entity, Added<MotionPlayer>>) {
+    for entity in q_players.iter() {
+        commands.entity(entity).insert(FixedStepSnapshot::default());
+    }
+}
+
+/// Snapshots from before the timestep was switched are outdated.
+fn clear_fixed_step_snapshots(mut q_snapshots: Query<&mut FixedStepSnapshot>) {
+    for mut snapshot in q_snapshots.iter_mut() {
+        snapshot.previous.clear();
+        snapshot.current.clear();
+    }
+}
+
+/// Undo the render interpolation, so that every fixed step continues from the exact
+/// result of the previous one.
+fn restore_fixed_step(
+    q_snapshots: Query<&FixedStepSnapshot>,
+    mut q_transforms: Query<&mut Transform>,
+) {
+    for snapshot in q_snapshots.iter() {
+        for (entity, transform) in snapshot.current.iter() {
+            if let Ok(mut current_transform) = q_transforms.get_mut(*entity) {
+                *current_transform = *transform;
+            }
+        }
+    }
+}
+
+fn snapshot_fixed_step(
+    motion_data: MotionData,
+    mut q_players: Query<(&mut FixedStepSnapshot, &JointMap, &Transform2d, Entity)>,
+    q_transforms: Query<&Transform>,
+) {
+    let Some(motion_asset) = motion_data.get() else {
+        return;
+    };
+
+    for (mut snapshot, joint_map, transform2d, entity) in q_players.iter_mut() {
+        let snapshot = &mut *snapshot;
+        std::mem::swap(&mut snapshot.previous, &mut snapshot.current);
+        snapshot.current.clear();
+
+        // The entity transform is only synced with its [`Transform2d`] in PostUpdate,
+        // which may not run between 2 fixed steps.
+        if let Ok(mut root_transform) = q_transforms.get(entity).copied() {
+            root_transform.translation.x = transform2d.translation.x;
+            root_transform.translation.z = transform2d.translation.y;
+            root_transform.rotation = Quat::from_rotation_y(transform2d.angle);
+            snapshot.current.push((entity, root_transform));
+        }
+
+        for joint in motion_asset.joints() {
+            if let Some((&joint_entity, Ok(transform))) = joint_map
+                .get(joint.name())
+                .map(|joint_entity| (joint_entity, q_transforms.get(*joint_entity)))
+            {
+                snapshot.current.push((joint_entity, *transform));
+            }
+        }
+    }
+}
+
+/// Interpolate between the last 2 fixed steps by the time left over in the fixed timestep.
+pub(crate) fn interpolate_fixed_step(
+    q_snapshots: Query<&FixedStepSnapshot>,
+    mut q_transforms: Query<&mut Transform>,
+    fixed_time: Res<Time<Fixed>>,
+) {
+    let factor = fixed_time.overstep_fraction();
+
+    for snapshot in q_snapshots.iter() {
+        for (i, (entity, current)) in snapshot.current.iter().enumerate() {
+            let previous = snapshot
+                .previous
+                .get(i)
+                .filter(|(previous_entity, _)| previous_entity == entity)
+                .map_or(current, |(_, previous)| previous);
+
+            if let Ok(mut transform) = q_transforms.get_mut(*entity) {
+                *transform = Transform {
+                    translation: Vec3::lerp(previous.translation, current.translation, factor),
+                    rotation: Quat::slerp(previous.rotation, current.rotation, factor),
+                    scale: Vec3::lerp(previous.scale, current.scale, factor),
+                };
+            }
+        }
+    }
+}
+
+/// When to run the [`MotionUpdate`] schedule.
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionTimestep {
+    /// Every frame with the frame's delta time.
+    #[default]
+    Variable,
+    /// In [`FixedUpdate`] at the rate of [`Time<Fixed>`], so that the same input gives
+    /// the same result regardless of the frame rate.
+    ///
+    /// Transforms are interpolated between the last 2 steps for rendering.
+    Fixed,
+}
+
+/// Transforms of a motion player (and its joints) after the last 2 fixed steps.
+#[derive(Component, Default, Debug)]
+pub struct FixedStepSnapshot {
+    previous: Vec<(Entity, Transform)>,
+    current: Vec<(Entity, Transform)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::motion::motion_player::TrajectoryPoseStack;
+    use crate::test_app::{motion_app, run_for};
+
+    use super::*;
+
+    /// Duration of a fixed step, frame deltas below are multiples or fractions of it.
+    const FIXED_STEP: Duration = Duration::from_millis(16);
+
+    #[test]
+    fn fixed_timestep_is_independent_of_frame_rate() {
+        let duration = FIXED_STEP * 300;
+        let results = [FIXED_STEP * 2, FIXED_STEP / 2, FIXED_STEP / 4].map(|frame_delta| {
+            let (mut app, player) = motion_app(FIXED_STEP);
+            run_for(&mut app, frame_delta, duration);
+
+            let world = app.world();
+            let transform2d = *world.get::<Transform2d>(player).unwrap();
+            let motion_pose = *world
+                .get::<TrajectoryPoseStack>(player)
+                .unwrap()
+                .target()
+                .expect("A trajectory should have been matched.")
+                .motion_pose();
+            (transform2d, motion_pose)
+        });
+
+        let (transform2d, motion_pose) = results[0];
+        assert!(
+            transform2d.translation.length() > 1.0,
+            "The player should have moved."
+        );
+        for (other_transform2d, other_motion_pose) in &results[1..] {
+            assert_eq!(transform2d.translation, other_transform2d.translation);
+            assert_eq!(transform2d.angle, other_transform2d.angle);
+            assert_eq!(motion_pose.chunk_index, other_motion_pose.chunk_index);
+            assert_eq!(motion_pose.time, other_motion_pose.time);
+        }
+    }
+}
@@ -1,13 +1,11 @@
 use bevy::prelude::*;
-use leafwing_input_manager::prelude::*;
 
-use crate::action::PlayerAction;
 use crate::draw_axes::{ColorPalette, DrawAxes};
-use crate::player::MovementConfig;
+use crate::player::{MovementConfig, MovementInput, MovementSpeed};
 use crate::record::{Records, RecordsBundle};
 use crate::transform2d::Transform2d;
 use crate::ui::config::DrawTrajectory;
-use crate::MainSet;
+use crate::{MainSet, MotionUpdate, LARGE_EPSILON};
 
 pub struct TrajectoryPlugin;
 
@@ -20,67 +18,166 @@ impl Plugin for TrajectoryPlugin {
         })
         .init_resource::<TrajectoryPlot>()
         .add_systems(
-            Update,
+            MotionUpdate,
             (
                 resize_trajectory.run_if(resource_changed::<TrajectoryConfig>),
+                update_movement_speeds,
                 (predict_trajectory, current_trajectory, history_trajectory),
             )
                 .chain()
                 .in_set(MainSet::Trajectory),
         )
-        .add_systems(Last, (update_velocities, update_prev_transform2ds).chain())
+        .add_systems(
+            MotionUpdate,
+            (update_velocities, update_prev_transform2ds)
+                .chain()
+                .after(MainSet::Animation),
+        )
         .add_systems(Update, (draw_trajectory_axes, draw_trajectory_plot));
 
         app.register_type::<Trajectory>()
             .register_type::<PrevTransform2d>()
             .register_type::<Velocity>()
-            .register_type::<MovementDirection>();
+            .register_type::<MovementDirection>()
+            .register_type::<TrajectoryPredictor>()
+            .register_type::<PredictedFacing>();
     }
 }
 
-fn predict_trajectory(
-    mut q_trajectories: Query<(&mut Trajectory, &Transform2d, &Velocity, &MovementDirection)>,
-    action: Res<ActionState<PlayerAction>>,
-    trajectory_config: Res<TrajectoryConfig>,
+/// Accelerate each entity towards the speed of its requested [`SpeedTier`].
+fn update_movement_speeds(
+    mut q_movement_speeds: Query<(&mut MovementSpeed, &MovementInput)>,
     movement_config: Res<MovementConfig>,
     time: Res<Time>,
-    mut speed: Local<f32>,
 ) {
-    let damping = match action.axis_pair(&PlayerAction::Walk) != Vec2::ZERO {
-        true => 0.9,
-        false => 0.6,
-    };
+    for (mut movement_speed, movement_input) in q_movement_speeds.iter_mut() {
+        **movement_speed = movement_speed.lerp(
+            movement_config.speed(movement_input.tier),
+            f32::min(1.0, time.delta_secs() * movement_config.lerp_factor),
+        );
+    }
+}
 
-    let target_speed = match action.pressed(&PlayerAction::Run) {
-        true => movement_config.run_speed,
-        false => movement_config.walk_speed,
-    };
+fn predict_trajectory(
+    mut q_trajectories: Query<(
+        &mut Trajectory,
+        &mut PredictedFacing,
+        &TrajectoryPredictor,
+        &Transform2d,
+        &Velocity,
+        &MovementDirection,
+        &MovementSpeed,
+        &MovementInput,
+    )>,
+    trajectory_config: Res<TrajectoryConfig>,
+) {
+    for (
+        mut trajectory,
+        mut predicted_facing,
+        predictor,
+        transform2d,
+        velocity,
+        direction,
+        movement_speed,
+        movement_input,
+    ) in q_trajectories.iter_mut()
+    {
+        let speed = movement_speed.get();
+        predicted_facing.resize(trajectory_config.predict_count, 0.0);
+
+        match *predictor {
+            TrajectoryPredictor::Damping => {
+                let damping = match movement_input.moving {
+                    true => 0.9,
+                    false => 0.6,
+                };
+
+                // Predict trajectory.
+                let mut translation = transform2d.translation;
+                let mut velocity = **velocity;
+
+                let velocity_addition = **direction * speed;
+
+                for i in 0..trajectory_config.predict_count {
+                    velocity += velocity_addition;
+                    // Accelerate to max speed.
+                    velocity = Vec2::clamp_length(velocity, 0.0, speed);
+                    translation += velocity * trajectory_config.interval_time;
+                    velocity *= damping;
+
+                    trajectory[i + trajectory_config.history_count + 1] = TrajectoryPoint {
+                        translation,
+                        velocity,
+                    };
+                    // Face the direction of travel.
+                    predicted_facing[i] = match velocity.length_squared() > LARGE_EPSILON {
+                        true => f32::atan2(velocity.x, velocity.y),
+                        false => transform2d.angle,
+                    };
+                }
+            }
+            TrajectoryPredictor::Spring {
+                velocity_halflife,
+                facing_halflife,
+            } => {
+                let target_velocity = **direction * speed;
+                let target_facing = match direction.length_squared() > LARGE_EPSILON {
+                    true => f32::atan2(direction.x, direction.y),
+                    false => transform2d.angle,
+                };
+
+                for i in 0..trajectory_config.predict_count {
+                    let t = (i + 1) as f32 * trajectory_config.interval_time;
+
+                    let (offset, velocity) =
+                        spring_velocity(**velocity, target_velocity, velocity_halflife, t);
+
+                    trajectory[i + trajectory_config.history_count + 1] = TrajectoryPoint {
+                        translation: transform2d.translation + offset,
+                        velocity,
+                    };
+                    predicted_facing[i] =
+                        spring_angle(transform2d.angle, target_facing, facing_halflife, t);
+                }
+            }
+        }
+    }
+}
 
-    *speed = speed.lerp(
-        target_speed,
-        time.delta_secs() * movement_config.lerp_factor,
-    );
+/// Convert a halflife into the damping of a critically damped spring (divided by 2).
+#[inline]
+fn halflife_to_damping(halflife: f32) -> f32 {
+    (2.0 * std::f32::consts::LN_2) / (halflife + f32::EPSILON)
+}
 
-    for (mut trajectory, transform2d, velocity, direction) in q_trajectories.iter_mut() {
-        // Predict trajectory.
-        let mut translation = transform2d.translation;
-        let mut velocity = **velocity;
+/// Evaluate a critically damped spring pulling `velocity` towards `target_velocity`
+/// after time `t`, starting without acceleration.
+///
+/// Returns the translation offset and the velocity at time `t`.
+fn spring_velocity(velocity: Vec2, target_velocity: Vec2, halflife: f32, t: f32) -> (Vec2, Vec2) {
+    let y = halflife_to_damping(halflife);
+    let j0 = velocity - target_velocity;
+    let j1 = j0 * y;
+    let eydt = f32::exp(-y * t);
+
+    let offset =
+        eydt * ((-j1) / (y * y) + (-j0 - j1 * t) / y) + j1 / (y * y) + j0 / y + target_velocity * t;
+    let velocity = eydt * (j0 + j1 * t) + target_velocity;
+
+    (offset, velocity)
+}
 
-        let velocity_addition = **direction * *speed;
+/// Evaluate a critically damped spring pulling `angle` towards `target_angle`
+/// after time `t`, starting without angular velocity.
+fn spring_angle(angle: f32, target_angle: f32, halflife: f32, t: f32) -> f32 {
+    use std::f32::consts::{PI, TAU};
 
-        for i in 0..trajectory_config.predict_count {
-            velocity += velocity_addition;
-            // Accelerate to max speed.
-            velocity = Vec2::clamp_length(velocity, 0.0, *speed);
-            translation += velocity * trajectory_config.interval_time;
-            velocity *= damping;
+    let y = halflife_to_damping(halflife);
+    // Shortest way around.
+    let j0 = (angle - target_angle + PI).rem_euclid(TAU) - PI;
+    let j1 = j0 * y;
 
-            trajectory[i + trajectory_config.history_count + 1] = TrajectoryPoint {
-                translation,
-                velocity,
-            };
-        }
-    }
+    f32::exp(-y * t) * (j0 + j1 * t) + target_angle
 }
 
 fn current_trajectory(
@@ -153,7 +250,11 @@ fn history_trajectory(
             }
 
             // Lerp between start and end point.
-            let factor = 1.0 - (record_time - target_time) / curr_delta_time;
+            let factor = match curr_delta_time > 0.0 {
+                true => 1.0 - (record_time - target_time) / curr_delta_time,
+                // Records that have not been filled yet have no duration.
+                false => 0.0,
+            };
             trajectory[trajectory_config.history_count - i] = TrajectoryPoint {
                 translation: Vec2::lerp(trans_start, trans_end, factor),
                 velocity: Vec2::lerp(vel_start, vel_end, factor),
@@ -176,16 +277,17 @@ fn resize_trajectory(
 }
 
 fn draw_trajectory_axes(
-    q_trajectories: Query<&Trajectory>,
+    q_trajectories: Query<(&Trajectory, Option<&PredictedFacing>)>,
     mut axes: ResMut<DrawAxes>,
     movement_config: Res<MovementConfig>,
+    trajectory_config: Res<TrajectoryConfig>,
     palette: Res<ColorPalette>,
     draw_trajectory: Res<DrawTrajectory>,
 ) {
     if !**draw_trajectory {
         return;
     }
-    for trajectory in q_trajectories.iter() {
+    for (trajectory, predicted_facing) in q_trajectories.iter() {
         for point in trajectory.iter() {
             let angle = f32::atan2(point.velocity.x, point.velocity.y);
             let translation = Vec3::new(point.translation.x, 0.0, point.translation.y);
@@ -194,9 +296,28 @@ fn draw_trajectory_axes(
             axes.draw_forward(
                 Mat4::from_rotation_translation(Quat::from_rotation_y(angle), translation),
                 velocity_magnitude * 0.1,
-                palette
-                    .blue
-                    .mix(&palette.red, velocity_magnitude / movement_config.run_speed),
+                palette.blue.mix(
+                    &palette.red,
+                    velocity_magnitude / movement_config.sprint_speed,
+                ),
+            );
+        }
+
+        let Some(predicted_facing) = predicted_facing else {
+            continue;
+        };
+
+        // Heading of each prediction point.
+        for (point, &facing) in trajectory
+            .iter()
+            .skip(trajectory_config.history_count + 1)
+            .zip(predicted_facing.iter())
+        {
+            let translation = Vec3::new(point.translation.x, 0.0, point.translation.y);
+            axes.draw_forward(
+                Mat4::from_rotation_translation(Quat::from_rotation_y(facing), translation),
+                0.1,
+                palette.green,
             );
         }
     }
@@ -253,6 +374,10 @@ pub struct TrajectoryBundle {
     pub prev_transform2d: PrevTransform2d,
     pub velocity: Velocity,
     pub movement_direction: MovementDirection,
+    pub movement_speed: MovementSpeed,
+    pub movement_input: MovementInput,
+    pub predictor: TrajectoryPredictor,
+    pub predicted_facing: PredictedFacing,
     pub transform2d_records: RecordsBundle<Transform2d>,
     pub velocity_records: RecordsBundle<Velocity>,
 }
@@ -265,6 +390,10 @@ impl TrajectoryBundle {
             prev_transform2d: PrevTransform2d::default(),
             velocity: Velocity::default(),
             movement_direction: MovementDirection::default(),
+            movement_speed: MovementSpeed::default(),
+            movement_input: MovementInput::default(),
+            predictor: TrajectoryPredictor::default(),
+            predicted_facing: PredictedFacing::default(),
             transform2d_records: RecordsBundle::new(record_len),
             velocity_records: RecordsBundle::new(record_len),
         }
@@ -288,6 +417,35 @@ pub struct Velocity(Vec2);
 #[reflect(Component)]
 pub struct MovementDirection(Vec2);
 
+/// Facing angles of the prediction points in the [`Trajectory`].
+#[derive(Component, Reflect, Default, Debug, Deref, DerefMut, Clone)]
+#[reflect(Component)]
+pub struct PredictedFacing(Vec<f32>);
+
+/// How the prediction points of a [`Trajectory`] are generated.
+#[derive(Component, Reflect, Default, Debug, Clone, Copy, PartialEq)]
+#[reflect(Component)]
+pub enum TrajectoryPredictor {
+    /// Accumulate velocity towards the movement direction with a fixed damping.
+    #[default]
+    Damping,
+    /// Critically damped springs towards the desired velocity and facing,
+    /// evaluated analytically at each prediction point.
+    Spring {
+        /// Time in seconds to cover half of the distance to the desired velocity.
+        velocity_halflife: f32,
+        /// Time in seconds to cover half of the angle to the desired facing.
+        facing_halflife: f32,
+    },
+}
+
+impl TrajectoryPredictor {
+    pub const DEFAULT_SPRING: Self = Self::Spring {
+        velocity_halflife: 0.2,
+        facing_halflife: 0.1,
+    };
+}
+
 /// A single point in the [`Trajectory`].
 #[derive(Reflect, Default, Debug, Clone, Copy)]
 pub struct TrajectoryPoint {
@@ -408,3 +566,65 @@ impl TrajectoryConfig {
 
 #[derive(Resource, Debug, Default, Deref, DerefMut)]
 pub struct TrajectoryPlot(Vec<[f64; 2]>);
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+
+    #[test]
+    fn spring_velocity_starts_at_current_velocity() {
+        let velocity = Vec2::new(1.0, -2.0);
+        let (offset, sampled) = spring_velocity(velocity, Vec2::X * 3.0, 0.2, 0.0);
+
+        assert!(offset.length() < 1e-5);
+        assert!(sampled.distance(velocity) < 1e-5);
+    }
+
+    #[test]
+    fn spring_velocity_settles_at_target() {
+        let target = Vec2::new(0.0, 2.0);
+        let (_, velocity) = spring_velocity(Vec2::new(-1.0, 0.5), target, 0.1, 5.0);
+
+        assert!(velocity.distance(target) < 1e-4);
+    }
+
+    #[test]
+    fn spring_velocity_offset_is_integrated_velocity() {
+        let (velocity, target, halflife) = (Vec2::new(2.0, 0.0), Vec2::new(0.0, 1.5), 0.3);
+        let (t, steps) = (1.0, 10_000);
+        let dt = t / steps as f32;
+
+        // Trapezoidal integration of the sampled velocity.
+        let mut integrated = Vec2::ZERO;
+        for step in 0..steps {
+            let (_, v0) = spring_velocity(velocity, target, halflife, step as f32 * dt);
+            let (_, v1) = spring_velocity(velocity, target, halflife, (step + 1) as f32 * dt);
+            integrated += (v0 + v1) * 0.5 * dt;
+        }
+
+        let (offset, _) = spring_velocity(velocity, target, halflife, t);
+        assert!(offset.distance(integrated) < 1e-3);
+    }
+
+    #[test]
+    fn spring_angle_takes_the_shortest_way() {
+        let (angle, target) = (3.0, -3.0);
+
+        let start = spring_angle(angle, target, 0.2, 0.0);
+        assert!(
+            (start - angle)
+                .rem_euclid(TAU)
+                .min((angle - start).rem_euclid(TAU))
+                < 1e-5
+        );
+
+        // Crossing PI instead of passing through 0.
+        let halfway = spring_angle(angle, target, 0.2, 0.1);
+        assert!(halfway.abs() > 3.0);
+
+        let end = spring_angle(angle, target, 0.2, 5.0);
+        assert!((end - target).abs() < 1e-4);
+    }
+}
@@ -1,3 +1,4 @@
+use bevy::math::Affine2;
 use bevy::prelude::*;
 
 pub struct Transform2dPlugin;
@@ -13,7 +14,7 @@ impl Plugin for Transform2dPlugin {
     }
 }
 
-fn apply_transform2d(
+pub(crate) fn apply_transform2d(
     mut q_transform2ds: Query<(&mut Transform, &Transform2d), Changed<Transform2d>>,
 ) {
     for (mut transform, transform2d) in q_transform2ds.iter_mut() {
@@ -53,4 +54,20 @@ impl Transform2d {
         let forward = self.forward();
         Vec2::new(forward.y, -forward.x)
     }
+
+    /// Convert into an [`Affine2`] on the xz plane.
+    pub fn to_affine2(&self) -> Affine2 {
+        // Positive angles rotate from z towards x, which is clockwise on the xz plane.
+        Affine2::from_angle_translation(-self.angle, self.translation)
+    }
+
+    /// Convert from an [`Affine2`] on the xz plane (see [`Self::to_affine2`]).
+    pub fn from_affine2(affine2: Affine2) -> Self {
+        let x_axis = affine2.matrix2.x_axis;
+
+        Self {
+            translation: affine2.translation,
+            angle: -f32::atan2(x_axis.y, x_axis.x),
+        }
+    }
 }
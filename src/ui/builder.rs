@@ -7,8 +7,13 @@ use bevy_bvh_anim::prelude::*;
 use bevy_egui::egui;
 
 use crate::bvh_manager::bvh_library::BvhLibrary;
+use crate::motion::animation_notify::{derive_contact_markers, AnimationMarker};
+use crate::motion::foot_ik::FootIkConfig;
 use crate::motion::motion_asset::MotionAsset;
 use crate::motion::trajectory_data::TrajectoryDataConfig;
+use crate::motion_matching::kdtree_match::KdTreeIndex;
+use crate::motion_matching::kmeans_match::{KMeansConfig, KMeansIndex};
+use crate::motion_matching::search_index::{save_search_index, MOTION_DATA_PATH};
 use crate::trajectory::TrajectoryConfig;
 
 use super::scrollbox;
@@ -17,6 +22,10 @@ use super::scrollbox;
 pub struct BuildConfigs {
     pub selections: HashMap<AssetId<BvhAsset>, bool>,
     pub bvh_assets: HashSet<AssetId<BvhAsset>>,
+    /// Markers authored for each bvh.
+    pub markers: HashMap<AssetId<BvhAsset>, Vec<AnimationMarker>>,
+    /// Add a marker wherever a foot gets planted.
+    pub derive_contact_markers: bool,
 }
 
 pub struct BuildConfig {
@@ -59,8 +68,46 @@ fn motion_data_asset_buider_menu(ui: &mut egui::Ui, world: &mut World) {
                     build_config.bvh_assets.remove(&id);
                 }
             }
+
+            if is_selected {
+                ui.indent(id, |ui| {
+                    marker_editor(ui, build_config.markers.entry(id).or_default());
+                });
+            }
         }
     });
+
+    ui.checkbox(
+        &mut build_config.derive_contact_markers,
+        "Derive Foot Contact Markers",
+    );
+}
+
+fn marker_editor(ui: &mut egui::Ui, markers: &mut Vec<AnimationMarker>) {
+    let mut removed = None;
+
+    for (i, marker) in markers.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut marker.name).desired_width(120.0));
+            ui.add(
+                egui::DragValue::new(&mut marker.time)
+                    .speed(0.01)
+                    .range(0.0..=f32::MAX)
+                    .suffix("s"),
+            );
+            if ui.button("Remove").clicked() {
+                removed = Some(i);
+            }
+        });
+    }
+
+    if let Some(i) = removed {
+        markers.remove(i);
+    }
+
+    if ui.button("Add Marker").clicked() {
+        markers.push(AnimationMarker::default());
+    }
 }
 
 fn build_motion_data_asset_button(ui: &mut egui::Ui, world: &mut World) {
@@ -69,8 +116,11 @@ fn build_motion_data_asset_button(ui: &mut egui::Ui, world: &mut World) {
         Res<Assets<BvhAsset>>,
         Res<BuildConfigs>,
         Res<TrajectoryConfig>,
+        Res<KMeansConfig>,
+        Res<FootIkConfig>,
     )>::new(world);
-    let (bvh_library, bvh_assets, build_config, trajectory_config) = params.get(world);
+    let (bvh_library, bvh_assets, build_config, trajectory_config, kmeans_config, foot_ik_config) =
+        params.get(world);
 
     if ui.button("Build").clicked() {
         let Some(bvh_map) = bvh_library
@@ -89,12 +139,18 @@ fn build_motion_data_asset_button(ui: &mut egui::Ui, world: &mut World) {
             },
         );
 
-        motion_data_asset.append_bvhs(
-            build_config
-                .bvh_assets
-                .iter()
-                .filter_map(|id| bvh_assets.get(*id)),
-        );
+        motion_data_asset.append_bvhs(build_config.bvh_assets.iter().filter_map(|id| {
+            let markers = build_config
+                .markers
+                .get(id)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            Some((bvh_assets.get(*id)?, markers))
+        }));
+
+        if build_config.derive_contact_markers {
+            derive_contact_markers(&mut motion_data_asset, &foot_ik_config);
+        }
 
         let convert_to_json = serde_json::to_string(&motion_data_asset).unwrap();
 
@@ -103,9 +159,23 @@ fn build_motion_data_asset_button(ui: &mut egui::Ui, world: &mut World) {
             .create(true)
             .truncate(true)
             // TODO: specify a file name and possibly a location
-            .open("assets/motion_data/motion_data.json")
+            .open(format!("assets/{MOTION_DATA_PATH}.json"))
             .unwrap();
 
         asset_file.write_all(convert_to_json.as_bytes()).unwrap();
+
+        // Build search indexes once here instead of on every launch.
+        if let Err(err) =
+            save_search_index(&KdTreeIndex::build(&motion_data_asset, &trajectory_config))
+        {
+            warn!("Could not save KD-Tree index: {err}");
+        }
+        if let Err(err) = save_search_index(&KMeansIndex::build(
+            &motion_data_asset,
+            &trajectory_config,
+            &kmeans_config,
+        )) {
+            warn!("Could not save K-Means index: {err}");
+        }
     }
 }
@@ -4,12 +4,23 @@ use bevy_egui::egui;
 use bevy_egui::egui::Color32;
 use egui_plot::{Arrows, Legend, Line, Plot, PlotPoints};
 
+use crate::motion::animation_layers::AnimationLayers;
 use crate::motion::chunk::ChunkIterator;
+use crate::motion::foot_ik::{FootIk, FootIkConfig};
+use crate::motion::motion_player::{BlendMode, MotionPlayerConfig};
+use crate::motion::simulation_bone::{RootMotionMode, SimulationBoneConfig};
 use crate::motion::MotionData;
+use crate::motion_matching::action_clip::{ActionClipLibrary, ActionClipState, PlayActionClip};
+use crate::motion_matching::brute_force_match::BruteForceConfig;
+use crate::motion_matching::kmeans_match::{KMeansConfig, KMeansResource};
 use crate::motion_matching::MatchTrajectory;
+use crate::player::{MovementConfig, MovementInput, MovementSpeed, PlayerMarker};
+use crate::replay::{Replay, ReplayControl, REPLAY_PATH};
 use crate::testing::generate_testing_data;
+use crate::timestep::MotionTimestep;
 use crate::trajectory::TrajectoryConfig;
 use crate::trajectory::TrajectoryPlot;
+use crate::trajectory::TrajectoryPredictor;
 use crate::{GameMode, Method, BVH_SCALE_RATIO};
 
 use super::groupbox;
@@ -23,7 +34,18 @@ pub fn play_mode_panel(ui: &mut egui::Ui, world: &mut World) {
     draw_nearest_pose_armature_checkbox(ui, world);
     draw_nearest_trajectory_checkbox(ui, world);
     run_preset_direction(ui, world);
+    trajectory_predictor(ui, world);
+    movement_speed(ui, world);
+    motion_timestep(ui, world);
+    replay_controls(ui, world);
+    motion_player_config(ui, world);
+    simulation_bone_config(ui, world);
+    foot_ik_config(ui, world);
+    animation_layers(ui, world);
+    action_clips(ui, world);
     motion_matching_method(ui, world);
+    brute_force_config(ui, world);
+    kmeans_config(ui, world);
     trajectory_matching_visualization(ui, world);
     motion_matching_result(ui, world);
 }
@@ -97,6 +119,271 @@ fn run_preset_direction(ui: &mut egui::Ui, world: &mut World) {
     ui.add_space(10.0);
 }
 
+fn trajectory_predictor(ui: &mut egui::Ui, world: &mut World) {
+    let mut q_predictors = world.query_filtered::<&mut TrajectoryPredictor, With<PlayerMarker>>();
+    for mut predictor in q_predictors.iter_mut(world) {
+        let mut spring = matches!(*predictor, TrajectoryPredictor::Spring { .. });
+        if ui
+            .checkbox(&mut spring, "Spring Trajectory Prediction")
+            .changed()
+        {
+            *predictor = match spring {
+                true => TrajectoryPredictor::DEFAULT_SPRING,
+                false => TrajectoryPredictor::Damping,
+            };
+        }
+
+        if let TrajectoryPredictor::Spring {
+            velocity_halflife,
+            facing_halflife,
+        } = &mut *predictor
+        {
+            ui.add(egui::Slider::new(velocity_halflife, 0.01..=1.0).text("Velocity Halflife"));
+            ui.add(egui::Slider::new(facing_halflife, 0.01..=1.0).text("Facing Halflife"));
+        }
+    }
+    ui.add_space(10.0);
+}
+
+fn movement_speed(ui: &mut egui::Ui, world: &mut World) {
+    let mut q_movement_speeds =
+        world.query_filtered::<(&MovementSpeed, &MovementInput), With<PlayerMarker>>();
+    for (movement_speed, movement_input) in q_movement_speeds.iter(world) {
+        ui.label(format!(
+            "Speed: {:.2} ({:?})",
+            movement_speed.get(),
+            movement_input.tier
+        ));
+    }
+
+    let mut movement_config = world.resource_mut::<MovementConfig>();
+    ui.add(egui::Slider::new(&mut movement_config.walk_speed, 0.1..=5.0).text("Walk Speed"));
+    ui.add(egui::Slider::new(&mut movement_config.run_speed, 0.1..=5.0).text("Run Speed"));
+    ui.add(egui::Slider::new(&mut movement_config.sprint_speed, 0.1..=5.0).text("Sprint Speed"));
+    ui.add_space(10.0);
+}
+
+fn motion_timestep(ui: &mut egui::Ui, world: &mut World) {
+    let mut motion_timestep = world.resource_mut::<MotionTimestep>();
+    let mut fixed = *motion_timestep == MotionTimestep::Fixed;
+    if ui.checkbox(&mut fixed, "Fixed Timestep").changed() {
+        *motion_timestep = match fixed {
+            true => MotionTimestep::Fixed,
+            false => MotionTimestep::Variable,
+        };
+    }
+
+    if fixed {
+        let mut fixed_time = world.resource_mut::<Time<Fixed>>();
+        let mut hz = fixed_time.timestep().as_secs_f64().recip();
+        if ui
+            .add(egui::Slider::new(&mut hz, 10.0..=240.0).text("Timestep Hz"))
+            .changed()
+        {
+            fixed_time.set_timestep_hz(hz);
+        }
+    }
+    ui.add_space(10.0);
+}
+
+fn replay_controls(ui: &mut egui::Ui, world: &mut World) {
+    ui.label("Replay");
+    groupbox(ui, |ui| {
+        let control = match world.resource::<Replay>() {
+            Replay::Idle => {
+                ui.label("Idle");
+                ui.horizontal(|ui| {
+                    if ui.button("Record").clicked() {
+                        return Some(ReplayControl::StartRecording);
+                    }
+                    if ui.button(format!("Replay {REPLAY_PATH}")).clicked() {
+                        return Some(ReplayControl::StartReplay);
+                    }
+                    None
+                })
+                .inner
+            }
+            Replay::Recording(file) => {
+                ui.label(format!("Recording: {} frames", file.frames.len()));
+                ui.button("Stop & Save")
+                    .clicked()
+                    .then_some(ReplayControl::StopRecording)
+            }
+            Replay::Replaying {
+                file,
+                step,
+                divergences,
+            } => {
+                ui.label(format!("Replaying: {step}/{} frames", file.frames.len()));
+                match divergences.first() {
+                    Some(divergence) => {
+                        ui.colored_label(
+                            Color32::LIGHT_RED,
+                            format!(
+                                "Diverged at {} steps, first at step {}",
+                                divergences.len(),
+                                divergence.step
+                            ),
+                        );
+                        ui.label(format!("Recorded: {:?}", divergence.recorded));
+                        ui.label(format!("Replayed: {:?}", divergence.replayed));
+                    }
+                    None => {
+                        ui.label("No divergence");
+                    }
+                }
+                ui.button("Stop")
+                    .clicked()
+                    .then_some(ReplayControl::StopReplay)
+            }
+        };
+
+        if let Some(control) = control {
+            world.send_event(control);
+        }
+    });
+    ui.add_space(10.0);
+}
+
+fn motion_player_config(ui: &mut egui::Ui, world: &mut World) {
+    let mut motion_player_config = world.resource_mut::<MotionPlayerConfig>();
+    let mut inertialization = motion_player_config.blend_mode == BlendMode::Inertialization;
+    ui.checkbox(&mut inertialization, "Inertialization Blending");
+
+    motion_player_config.blend_mode = match inertialization {
+        true => BlendMode::Inertialization,
+        false => BlendMode::CrossFade,
+    };
+
+    let max_playback_rate = motion_player_config.max_playback_rate;
+    ui.add(
+        egui::Slider::new(
+            &mut motion_player_config.min_playback_rate,
+            0.1..=max_playback_rate,
+        )
+        .text("Min Playback Rate"),
+    );
+    let min_playback_rate = motion_player_config.min_playback_rate;
+    ui.add(
+        egui::Slider::new(
+            &mut motion_player_config.max_playback_rate,
+            min_playback_rate..=3.0,
+        )
+        .text("Max Playback Rate"),
+    );
+    ui.add_space(10.0);
+}
+
+fn simulation_bone_config(ui: &mut egui::Ui, world: &mut World) {
+    let mut config = world.resource_mut::<SimulationBoneConfig>();
+    let mut simulation = config.mode == RootMotionMode::Simulation;
+    ui.checkbox(&mut simulation, "Simulation Bone");
+
+    config.mode = match simulation {
+        true => RootMotionMode::Simulation,
+        false => RootMotionMode::Animation,
+    };
+
+    if simulation {
+        groupbox(ui, |ui| {
+            ui.add(egui::Slider::new(&mut config.max_distance, 0.0..=1.0).text("Max Distance"));
+            ui.add(
+                egui::Slider::new(&mut config.max_angle, 0.0..=std::f32::consts::PI)
+                    .text("Max Angle"),
+            );
+            ui.add(
+                egui::Slider::new(&mut config.animation_pull, 0.0..=20.0).text("Animation Pull"),
+            );
+            ui.add(
+                egui::Slider::new(&mut config.simulation_pull, 0.0..=20.0).text("Simulation Pull"),
+            );
+        });
+    }
+    ui.add_space(10.0);
+}
+
+fn foot_ik_config(ui: &mut egui::Ui, world: &mut World) {
+    let mut q_foot_iks = world.query_filtered::<&mut FootIk, With<PlayerMarker>>();
+    for mut foot_ik in q_foot_iks.iter_mut(world) {
+        ui.checkbox(&mut foot_ik.enabled, "Foot IK");
+        if foot_ik.enabled {
+            ui.checkbox(&mut foot_ik.foot_locking, "Foot Locking");
+        }
+    }
+
+    let mut config = world.resource_mut::<FootIkConfig>();
+    ui.checkbox(&mut config.draw_gizmos, "Show Foot IK Gizmos");
+    ui.add_space(10.0);
+}
+
+fn animation_layers(ui: &mut egui::Ui, world: &mut World) {
+    let mut q_animation_layers = world.query_filtered::<&mut AnimationLayers, With<PlayerMarker>>();
+    for mut animation_layers in q_animation_layers.iter_mut(world) {
+        if animation_layers.iter().next().is_none() {
+            continue;
+        }
+
+        ui.label("Animation Layers");
+        groupbox(ui, |ui| {
+            for layer in animation_layers.iter_mut() {
+                ui.add(egui::Slider::new(&mut layer.weight, 0.0..=1.0).text(&layer.name));
+            }
+        });
+        ui.add_space(10.0);
+    }
+}
+
+fn action_clips(ui: &mut egui::Ui, world: &mut World) {
+    let mut params = SystemState::<(
+        MotionData,
+        Res<ActionClipLibrary>,
+        Query<(&ActionClipState, Entity), With<PlayerMarker>>,
+        EventWriter<PlayActionClip>,
+    )>::new(world);
+    let (motion_data, library, q_players, mut play_evw) = params.get_mut(world);
+
+    let Some(motion_asset) = motion_data.get() else {
+        return;
+    };
+
+    let names = library
+        .names()
+        .chain(motion_asset.animation_file.iter().map(String::as_str))
+        .collect::<Vec<_>>();
+    if names.is_empty() {
+        return;
+    }
+
+    let id = ui.id().with("action_clip");
+    let mut selected_index = ui
+        .data_mut(|data| data.get_temp::<usize>(id))
+        .unwrap_or_default()
+        .min(names.len() - 1);
+
+    for (action_clip_state, entity) in q_players.iter() {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_salt(id)
+                .selected_text(names[selected_index])
+                .show_index(ui, &mut selected_index, names.len(), |i| names[i]);
+
+            if ui.button("Play Action").clicked() {
+                play_evw.send(PlayActionClip {
+                    entity,
+                    name: names[selected_index].to_string(),
+                });
+            }
+        });
+
+        if let ActionClipState::Playing { name, .. } = action_clip_state {
+            ui.label(format!("Playing Action: {name}"));
+        }
+    }
+
+    ui.data_mut(|data| data.insert_temp(id, selected_index));
+    params.apply(world);
+    ui.add_space(10.0);
+}
+
 fn motion_matching_method(ui: &mut egui::Ui, world: &mut World) {
     let mut params = SystemState::<(
         ResMut<MotionMatchingResult>,
@@ -134,6 +421,73 @@ fn motion_matching_method(ui: &mut egui::Ui, world: &mut World) {
     ui.add_space(10.0);
 }
 
+fn brute_force_config(ui: &mut egui::Ui, world: &mut World) {
+    if *world.resource::<State<Method>>().get() != Method::BruteForceKNN {
+        return;
+    }
+
+    let mut brute_force_config = world.resource_mut::<BruteForceConfig>();
+    ui.checkbox(
+        &mut brute_force_config.benchmark,
+        "Benchmark Against Reference Scan",
+    );
+    ui.add_space(10.0);
+}
+
+fn kmeans_config(ui: &mut egui::Ui, world: &mut World) {
+    if *world.resource::<State<Method>>().get() != Method::KMeans {
+        return;
+    }
+
+    let mut params =
+        SystemState::<(ResMut<KMeansConfig>, Option<Res<KMeansResource>>, Commands)>::new(world);
+    let (mut kmeans_config, kmeans, mut commands) = params.get_mut(world);
+
+    groupbox(ui, |ui| {
+        ui.label("K-Means");
+
+        // Clusters are only rebuilt on request, not on every slider step.
+        let pending_id = ui.id().with("pending_kmeans_config");
+        let (mut k, mut max_iter) = ui
+            .data(|data| data.get_temp::<(usize, usize)>(pending_id))
+            .unwrap_or((kmeans_config.k, kmeans_config.max_iter));
+        ui.add(egui::Slider::new(&mut k, 1..=100).text("Clusters (k)"));
+        ui.add(egui::Slider::new(&mut max_iter, 1..=500).text("Max Iterations"));
+        ui.data_mut(|data| data.insert_temp(pending_id, (k, max_iter)));
+
+        let mut probe_count = kmeans_config.probe_count;
+        ui.add(egui::Slider::new(&mut probe_count, 1..=k).text("Probed Clusters"));
+        // Changing the config invalidates the index, so only touch it on actual edits.
+        if probe_count != kmeans_config.probe_count {
+            kmeans_config.probe_count = probe_count;
+        }
+
+        match kmeans {
+            Some(kmeans) => {
+                ui.label(format!(
+                    "Current Index: k: {}, max_iter: {}",
+                    kmeans.k(),
+                    kmeans.max_iter()
+                ));
+                if ui.button("Rebuild Clusters").clicked() {
+                    if (k, max_iter) != (kmeans_config.k, kmeans_config.max_iter) {
+                        kmeans_config.k = k;
+                        kmeans_config.max_iter = max_iter;
+                    } else {
+                        commands.remove_resource::<KMeansResource>();
+                    }
+                }
+            }
+            None => {
+                ui.label("Building clusters...");
+            }
+        }
+    });
+
+    params.apply(world);
+    ui.add_space(10.0);
+}
+
 fn trajectory_matching_visualization(ui: &mut egui::Ui, world: &mut World) {
     let mut params = SystemState::<(
         MotionData,
@@ -156,7 +510,7 @@ fn trajectory_matching_visualization(ui: &mut egui::Ui, world: &mut World) {
         let Some(selected_traj) = motion_matching_result
             .trajectories_poses
             .get(motion_matching_result.selected_trajectory)
-            .map(|(traj, _)| traj)
+            .map(|(traj, ..)| traj)
         else {
             return;
         };
@@ -247,6 +601,7 @@ fn motion_matching_result(ui: &mut egui::Ui, world: &mut World) {
             .column(Column::auto())
             .column(Column::auto())
             .column(Column::auto())
+            .column(Column::auto())
             .header(20.0, |mut header| {
                 header.col(|ui| {
                     ui.heading(egui::RichText::new("File Name").size(12.0).strong());
@@ -264,9 +619,13 @@ fn motion_matching_result(ui: &mut egui::Ui, world: &mut World) {
                     ui.heading(egui::RichText::new("Pose Dist").size(12.0).strong());
                     ui.separator();
                 });
+                header.col(|ui| {
+                    ui.heading(egui::RichText::new("Cooldown").size(12.0).strong());
+                    ui.separator();
+                });
             })
             .body(|mut body| {
-                for (i, (trajectory, pose_dist)) in
+                for (i, (trajectory, pose_dist, cooldown_penalty)) in
                     motion_matching_result.trajectories_poses.iter().enumerate()
                 {
                     let row_color = match i == motion_matching_result.selected_trajectory {
@@ -296,6 +655,11 @@ fn motion_matching_result(ui: &mut egui::Ui, world: &mut World) {
                             ui.label(format!("{:.3}", pose_dist));
                             ui.separator();
                         });
+                        row.col(|ui| {
+                            ui.visuals_mut().override_text_color = row_color;
+                            ui.label(format!("{:.3}", cooldown_penalty));
+                            ui.separator();
+                        });
                     });
                 }
             });
@@ -308,6 +672,13 @@ fn motion_matching_result(ui: &mut egui::Ui, world: &mut World) {
         result.avg_time,
     ));
     ui.label(format!("Average Memory Usage: {:.3} MB", result.avg_memory,));
+    if result.reference_runs > 0 {
+        ui.label(format!(
+            "Average Reference Matching Time: {:.3} ms ({:.1}x speed-up)",
+            result.avg_reference_time,
+            result.avg_reference_time / result.avg_time,
+        ));
+    }
 }
 
 #[derive(Resource, Deref, DerefMut)]
@@ -333,8 +704,8 @@ pub struct RunPresetDirection(pub bool);
 
 #[derive(Default, Resource)]
 pub struct MotionMatchingResult {
-    /// Match trajectories and pose distances.
-    pub trajectories_poses: Vec<(MatchTrajectory, f32)>,
+    /// Match trajectories, pose distances and cooldown penalties.
+    pub trajectories_poses: Vec<(MatchTrajectory, f32, f32)>,
     pub selected_trajectory: usize,
     pub matching_result: MatchingResult,
     // pub pose_matching_time: String,
@@ -345,4 +716,7 @@ pub struct MatchingResult {
     pub avg_time: f64,
     pub avg_memory: f64,
     pub runs: usize,
+    /// Average time of the single threaded reference scan (benchmark only).
+    pub avg_reference_time: f64,
+    pub reference_runs: usize,
 }
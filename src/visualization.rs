@@ -3,7 +3,10 @@ use bevy_bvh_anim::prelude::JointMatrices;
 
 use crate::{
     draw_axes::ColorPalette,
-    motion::{chunk::ChunkIterator, trajectory_data::TrajectoryDataPoint, MotionData},
+    motion::{
+        chunk::ChunkIterator, motion_player::MotionPose, trajectory_data::TrajectoryDataPoint,
+        MotionData,
+    },
     motion_matching::NearestTrajectories,
     player::PlayerMarker,
     trajectory::TrajectoryConfig,
@@ -154,12 +157,16 @@ fn draw_nearest_pose_armature(
 
     for (trajs, snapped_player_matrix, selected_pose) in nearest_traj.iter() {
         for (i, traj) in trajs.iter().enumerate() {
-            let pose = motion_asset
-                .pose_data
-                .get_chunk(traj.chunk_index)
-                .and_then(|poses| poses.get(traj.chunk_offset))
-                .unwrap();
-            joint_matrices.apply_frame(pose);
+            let Some(pose) = (MotionPose {
+                chunk_index: traj.chunk_index,
+                time: motion_asset
+                    .trajectory_data
+                    .time_from_chunk_offset(traj.chunk_offset),
+            })
+            .get_pose(&motion_asset.pose_data, motion_asset.joints()) else {
+                continue;
+            };
+            joint_matrices.apply_pos_rots(&pose);
 
             let pose_translation_offset = Vec3::new(i as f32 * POSE_OFFSET, 0.0, 0.0);
             for (joint_index, joint) in joint_matrices.joints().iter().enumerate() {